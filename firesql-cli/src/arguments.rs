@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::{command, Parser};
+use clap::Parser;
 
 /// Simple program to perform SQL queries on Firestore
 #[derive(Parser, Debug)]
@@ -14,7 +14,15 @@ pub(crate) struct Args {
     #[arg(short, long, value_name = "GCP service key")]
     pub(crate) gcp_service_account_key_file: Option<PathBuf>,
 
-    /// Path to file containing SQL select statement to run
+    /// Path to file containing SQL select statements to run (separated with `;`)
     #[arg(short, long)]
     pub(crate) input: Option<PathBuf>,
+
+    /// Maximum number of documents fetched for client side processing (e.g. GROUP BY).
+    #[arg(long, value_name = "count")]
+    pub(crate) max_documents: Option<u32>,
+
+    /// Maximum number of chained dereferences (e.g. `author->manager->name` is 2).
+    #[arg(long, value_name = "depth")]
+    pub(crate) max_dereference_depth: Option<u32>,
 }
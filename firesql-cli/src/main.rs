@@ -1,9 +1,7 @@
-use std::path::PathBuf;
-
 use ascii_table::AsciiTable;
 use clap::Parser;
 use color_eyre::eyre::Result;
-use firesql_core::{FireSQLParser, SQLExecutor as _};
+use firesql_core::{ExecutionOptions, FireSQLParser, Row, SQLExecutor as _};
 use firestore::{FirestoreDb, FirestoreDbOptions};
 
 mod arguments;
@@ -25,15 +23,44 @@ async fn main() -> Result<()> {
         None => FirestoreDb::new(args.firebase_project_id).await?,
     };
 
-    let sql = match args.input {
-        Some(input_file) => read_from_input_file(&input_file),
-        None => read_sql_from_stdin(),
+    let selects = match args.input {
+        Some(input_file) => {
+            let script = std::fs::read_to_string(input_file)?;
+            let mut selects = vec![];
+            for statement in FireSQLParser::parse_script(&script)? {
+                selects.push((script[statement.span()].to_owned(), statement.into_select()));
+            }
+            selects
+        }
+        None => {
+            let sql = read_sql_from_stdin()?;
+            let select = FireSQLParser::parse(&sql)?;
+            vec![(sql, select)]
+        }
     };
 
-    let sql = sql?;
-    let select = FireSQLParser::parse(&sql)?;
+    let options = match args.max_documents {
+        Some(max_documents) => {
+            ExecutionOptions::default().with_max_fetched_documents(max_documents)
+        }
+        None => ExecutionOptions::default(),
+    };
+    let options = match args.max_dereference_depth {
+        Some(depth) => options.with_max_dereference_depth(depth),
+        None => options,
+    };
 
-    let results = &firestore.execute(select).await?;
+    for (sql, select) in selects {
+        println!("{sql}");
+        let results = firestore
+            .execute_with_options(select, options.clone())
+            .await?;
+        print_results(&results);
+    }
+    Ok(())
+}
+
+fn print_results(results: &[Row]) {
     if results.is_empty() {
         println!("Nothing found!");
     }
@@ -53,7 +80,6 @@ async fn main() -> Result<()> {
             .chain(row.columns().iter().map(|(_, value)| value.clone()))
     });
     ascii_table.print(data);
-    Ok(())
 }
 
 fn read_sql_from_stdin() -> Result<String> {
@@ -67,9 +93,3 @@ fn read_sql_from_stdin() -> Result<String> {
         }
     }
 }
-
-fn read_from_input_file(input_file: &PathBuf) -> Result<String> {
-    let statement = std::fs::read_to_string(input_file)?;
-    println!("{statement}");
-    Ok(statement)
-}
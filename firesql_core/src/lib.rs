@@ -1,8 +1,15 @@
 mod sql_parser;
 mod sql_runner;
 
+pub use sql_parser::Aggregation;
 pub use sql_parser::FireSQLParseResult;
 pub use sql_parser::FireSQLParser;
 pub use sql_parser::FireSQLSelect;
+pub use sql_parser::FireSQLStatement;
+pub use sql_parser::Parameter;
+pub use sql_parser::ParseError;
+pub use sql_parser::Value;
+pub use sql_runner::ExecutionError;
+pub use sql_runner::ExecutionOptions;
 pub use sql_runner::Row;
 pub use sql_runner::SQLExecutor;
@@ -1,138 +1,1287 @@
 mod sql_grammar;
 
+use std::collections::BTreeMap;
+
+use itertools::{Either, Itertools as _};
 use pest::Parser;
 use sql_grammar::*;
 
 pub struct FireSQLParser;
 
+/// Name of the field used by Firestore to refer to the document id.
+pub(crate) const DOCUMENT_ID_FIELD: &str = "__name__";
+
+/// Maximum number of values in `IN` list accepted by Firestore.
+const IN_VALUES_LIMIT: usize = 30;
+/// Maximum number of values in `ARRAY_CONTAINS_ANY` list accepted by Firestore.
+const ARRAY_CONTAINS_ANY_VALUES_LIMIT: usize = 30;
+/// Maximum number of values in `NOT IN` list accepted by Firestore.
+const NOT_IN_VALUES_LIMIT: usize = 10;
+/// Maximum number of aggregations in a single Firestore aggregation query.
+const AGGREGATIONS_LIMIT: usize = 5;
+
 pub type FireSQLParseResult = Result<FireSQLSelect, ParseError>;
 
 impl FireSQLParser {
     pub fn parse(stmt: &str) -> Result<FireSQLSelect, ParseError> {
-        let parsed = FireSQLGrammarParser::parse(Rule::select_stmt, stmt.trim())
+        let select = FireSQLGrammarParser::parse(Rule::select_stmt, stmt.trim())
+            .map_err(|err| ParseError::GrammarError(Box::new(err)))?
+            .next()
+            .expect("select statement present")
+            .into_inner()
+            .next()
+            .expect("select expected");
+        parse_compound_select(select)
+    }
+
+    /// Parses `;` separated statements. Each statement is returned together with
+    /// its location in the script.
+    pub fn parse_script(script: &str) -> Result<Vec<FireSQLStatement>, ParseError> {
+        let parsed = FireSQLGrammarParser::parse(Rule::script, script)
             .map_err(|err| ParseError::GrammarError(Box::new(err)))?
             .next()
-            .expect("select statement present");
-        let select_stmt = match parsed.as_rule() {
-            Rule::select_stmt => {
-                let mut select_inner = parsed.into_inner();
-
-                let projections = select_inner.next().expect("select projections expected");
-                let projections = parse_projections(projections);
-
-                let tables = select_inner.next().expect("select tables expected");
-                let collection = parse_collection(tables)?;
-
-                let conditions = select_inner.next();
-                let conditions = conditions
-                    .map(|conditions| {
-                        use itertools::*;
-                        let conditions = conditions.into_inner();
-                        conditions
-                            .map(|condition| match condition.as_rule() {
-                                Rule::is_null => {
-                                    Ok(Condition::IsNull(condition.into_inner().to_string()))
-                                }
-                                Rule::is_not_null => Ok(Condition::Not(Box::new(
-                                    Condition::IsNull(condition.into_inner().to_string()),
-                                ))),
-                                Rule::comparison => {
-                                    let mut comparison_inner = condition.into_inner();
-                                    let property_name =
-                                        comparison_inner.next().expect("property expected");
-                                    let operator =
-                                        comparison_inner.next().expect("operator expected");
-                                    let value = comparison_inner.next().expect("value expected");
-                                    let inner_value =
-                                        value.into_inner().next().expect("inner value expected");
-                                    let value = match inner_value.as_rule() {
-                                        Rule::number => {
-                                            Ok(Value::Number(inner_value.as_str().parse().unwrap()))
-                                        }
-                                        Rule::string => Ok(Value::String(
-                                            inner_value
-                                                .into_inner()
-                                                .next()
-                                                .expect("inner_string expected")
-                                                .as_str()
-                                                .to_owned(),
-                                        )),
-                                        Rule::reference => {
-                                            Ok(Value::Reference(inner_value.as_str().to_owned()))
-                                        }
-                                        Rule::bool => {
-                                            Ok(Value::Bool(inner_value.as_str().parse().unwrap()))
-                                        }
-
-                                        rule => Err(ParseError::UnexpectedItem(format!(
-                                            "rule {:?} - {}",
-                                            rule,
-                                            inner_value.as_str()
-                                        ))),
-                                    }?;
-                                    let operation = match operator.as_str() {
-                                        "=" => Ok(CompareOperations::Equal(value)),
-                                        "!=" => Ok(CompareOperations::NotEqual(value)),
-                                        ">" => Ok(CompareOperations::GreaterThan(value)),
-                                        "<" => Ok(CompareOperations::LessThan(value)),
-                                        _ => Err(ParseError::UnexpectedItem(
-                                            operator.as_str().to_owned(),
-                                        )),
-                                    }?;
-                                    Ok(Condition::Comparison(
-                                        property_name.as_str().to_owned(),
-                                        operation,
-                                    ))
-                                }
-                                rule => Err(ParseError::UnexpectedItem(format!(
-                                    "rule {:?} - {}",
-                                    rule,
-                                    condition.as_str()
-                                ))),
-                            })
-                            .process_results(|c| c.collect_vec())
-                    })
-                    .unwrap_or(Ok(vec![]))?;
-
-                Ok(FireSQLSelect {
-                    projections,
-                    collection,
-                    conditions,
+            .expect("script present");
+        parsed
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::compound_select)
+            .map(|select| {
+                // the span of the rule may include trailing whitespace
+                let start = select.as_span().start();
+                let span = start..start + select.as_str().trim_end().len();
+                parse_compound_select(select).map(|select| FireSQLStatement { select, span })
+            })
+            .collect()
+    }
+}
+
+/// Parses the statement with the statements combined with it by set operations.
+fn parse_compound_select(
+    parsed: pest::iterators::Pair<'_, Rule>,
+) -> Result<FireSQLSelect, ParseError> {
+    let mut compound_inner = parsed.into_inner();
+    let mut select = parse_select(compound_term(
+        compound_inner.next().expect("select expected"),
+    ))?;
+    while let Some(operator) = compound_inner.next() {
+        let operator = match operator.into_inner().next().map(|pair| pair.as_rule()) {
+            Some(Rule::union_all) => SetOperator::UnionAll,
+            Some(Rule::union) => SetOperator::Union,
+            Some(Rule::intersect) => SetOperator::Intersect,
+            _ => SetOperator::Except,
+        };
+        let term = compound_inner.next().expect("select expected");
+        // in SQL these clauses would apply to the combined rows
+        let last = compound_inner.peek().is_none();
+        if last
+            && term.as_rule() == Rule::select
+            && term.clone().into_inner().any(|clause| {
+                matches!(
+                    clause.as_rule(),
+                    Rule::order_by_stmt | Rule::limit_stmt | Rule::offset_stmt
+                )
+            })
+        {
+            return Err(ParseError::InvalidSetOperation(
+                "ORDER BY, LIMIT and OFFSET of the last combined statement need parentheses"
+                    .to_owned(),
+            ));
+        }
+        let other = parse_select(compound_term(term))?;
+        validate_set_operation(&select, &other, operator)?;
+        select.set_operations.push((operator, other));
+    }
+    Ok(select)
+}
+
+/// Statement of the compound statement, possibly in parentheses.
+fn compound_term(term: pest::iterators::Pair<'_, Rule>) -> pest::iterators::Pair<'_, Rule> {
+    match term.as_rule() {
+        Rule::parenthesized_select => term.into_inner().next().expect("select expected"),
+        _ => term,
+    }
+}
+
+/// Combined statements select the same number of columns. Documents (`*`) have
+/// varying columns, so they can be combined only with documents.
+fn validate_set_operation(
+    select: &FireSQLSelect,
+    other: &FireSQLSelect,
+    operator: SetOperator,
+) -> Result<(), ParseError> {
+    if select.projections.len() != other.projections.len() {
+        return Err(ParseError::InvalidSetOperation(format!(
+            "{} combines statements selecting {} and {} columns",
+            operator,
+            select.projections.len(),
+            other.projections.len()
+        )));
+    }
+    let documents = |select: &FireSQLSelect| {
+        select
+            .projections
+            .iter()
+            .map(|projection| matches!(projection, SelectProjection::Object))
+            .collect_vec()
+    };
+    if documents(select) != documents(other) {
+        return Err(ParseError::InvalidSetOperation(format!(
+            "{} combines * with document fields",
+            operator
+        )));
+    }
+    Ok(())
+}
+
+fn parse_select(parsed: pest::iterators::Pair<'_, Rule>) -> Result<FireSQLSelect, ParseError> {
+    let select_stmt = match parsed.as_rule() {
+        Rule::select => {
+            let mut select_inner = parsed.into_inner();
+
+            let mut projections = select_inner.next().expect("select projections expected");
+            let distinct = projections.as_rule() == Rule::distinct;
+            if distinct {
+                projections = select_inner.next().expect("select projections expected");
+            }
+            let projections = parse_projections(projections);
+
+            let from_item = select_inner.next().expect("select tables expected");
+            let (collection, collection_alias) = parse_from_item(from_item)?;
+            let mut joins = vec![];
+
+            let mut conditions = vec![];
+            let mut group_by = vec![];
+            let mut having = None;
+            let mut order_by = vec![];
+            let mut start_at = None;
+            let mut end_at = None;
+            let mut limit = None;
+            let mut offset = None;
+            for clause in select_inner {
+                match clause.as_rule() {
+                    Rule::join => joins.push(parse_join(clause)?),
+                    Rule::where_stmt => conditions = parse_where(clause)?,
+                    Rule::group_by_stmt => {
+                        group_by = clause.into_inner().map(parse_field_path).collect()
+                    }
+                    Rule::having_stmt => having = Some(clause),
+                    Rule::order_by_stmt => order_by = parse_order_by(clause),
+                    Rule::start_cursor | Rule::end_cursor => {
+                        let rule = clause.as_rule();
+                        let cursor = parse_cursor(clause)?;
+                        match rule {
+                            Rule::start_cursor => start_at = Some(cursor),
+                            _ => end_at = Some(cursor),
+                        }
+                    }
+                    Rule::limit_stmt => limit = Some(parse_limit(clause)?),
+                    Rule::offset_stmt => {
+                        let integer = clause.into_inner().next().expect("offset expected");
+                        offset = Some(parse_integer(integer)?);
+                    }
+                    rule => {
+                        return Err(ParseError::UnexpectedItem(format!(
+                            "rule {:?} - {}",
+                            rule,
+                            clause.as_str()
+                        )))
+                    }
+                }
+            }
+
+            let resolver = PathResolver::new(&collection, collection_alias, &joins)?;
+            let joins = joins
+                .into_iter()
+                .enumerate()
+                .map(|(index, join)| resolver.resolve_join(index, join))
+                .collect::<Result<Vec<_>, _>>()?;
+            let projections = projections
+                .into_iter()
+                .map(|projection| resolver.resolve_projection(projection))
+                .collect::<Result<Vec<_>, _>>()?;
+            let group_by = group_by
+                .into_iter()
+                .map(|field| resolver.resolve(field))
+                .collect_vec();
+            // conditions on joined collections and referenced documents are checked
+            // on the client side
+            let (conditions, client_conditions): (Vec<_>, Vec<_>) = conditions
+                .into_iter()
+                .map(|condition| {
+                    let condition = condition.map_field_paths(&|field| resolver.resolve(field));
+                    let main_condition = !condition.has_dereference()
+                        && condition
+                            .field_paths()
+                            .into_iter()
+                            .all(|field| resolver.main_collection_path(field.clone()).is_some());
+                    match main_condition {
+                        true => Either::Left(condition.map_field_paths(&|field| {
+                            resolver
+                                .main_collection_path(field)
+                                .expect("main collection field")
+                        })),
+                        false => Either::Right(condition),
+                    }
                 })
+                .partition_map(|condition| condition);
+            let mut order_by = order_by
+                .into_iter()
+                .map(|order| {
+                    let field = resolver.resolve(order.field);
+                    match resolver.main_collection_path(field.clone()) {
+                        Some(field) => Ok(OrderBy { field, ..order }),
+                        None => Err(ParseError::InvalidJoin(format!(
+                            "ORDER BY {} - only fields of the main collection can be ordered",
+                            field
+                        ))),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let aggregations = projections
+                .iter()
+                .filter(|projection| matches!(projection, SelectProjection::Aggregate(..)))
+                .count();
+            if group_by.is_empty() {
+                if aggregations > 0 && aggregations < projections.len() {
+                    return Err(ParseError::InvalidProjection(
+                        "aggregations cannot be mixed with document fields without GROUP BY"
+                            .to_owned(),
+                    ));
+                }
+                if having.is_some() && aggregations == 0 {
+                    return Err(ParseError::InvalidGrouping(
+                        "HAVING requires GROUP BY or aggregations".to_owned(),
+                    ));
+                }
+                if aggregations > AGGREGATIONS_LIMIT {
+                    return Err(ParseError::InvalidProjection(format!(
+                        "{} aggregations, at most {} allowed",
+                        aggregations, AGGREGATIONS_LIMIT
+                    )));
+                }
+                // aggregations without GROUP BY make a single row, which cursors cannot point at
+                if aggregations > 0 && (start_at.is_some() || end_at.is_some()) {
+                    return Err(ParseError::InvalidCursor(
+                        "cursors cannot be used with aggregations without GROUP BY".to_owned(),
+                    ));
+                }
+            } else {
+                for projection in projections.iter() {
+                    match projection {
+                        SelectProjection::Aggregate(..) => {}
+                        SelectProjection::Property(field, _) if group_by.contains(field) => {}
+                        projection => {
+                            return Err(ParseError::InvalidGrouping(format!(
+                                "{} is neither grouped nor aggregated",
+                                projection.column_name()
+                            )))
+                        }
+                    }
+                }
+            }
+            if distinct
+                && (!group_by.is_empty()
+                    || projections.iter().any(|projection| {
+                        matches!(
+                            projection,
+                            SelectProjection::Object | SelectProjection::Aggregate(..)
+                        )
+                    }))
+            {
+                return Err(ParseError::InvalidProjection(
+                    "DISTINCT can be used only with document fields".to_owned(),
+                ));
+            }
+            let having = match having {
+                Some(having) => {
+                    let condition = having.into_inner().next().expect("condition expected");
+                    Some(parse_having_or_expr(
+                        condition,
+                        &projections,
+                        &group_by,
+                        &resolver,
+                    )?)
+                }
+                None => None,
+            };
+
+            let cursor_values = start_at
+                .iter()
+                .chain(end_at.iter())
+                .map(|cursor| cursor.values.len())
+                .max()
+                .unwrap_or_default();
+            let ordered = !order_by.is_empty();
+            if cursor_values > order_by.len() {
+                // an extra value is allowed to refer to the implicit document id order
+                if cursor_values > order_by.len() + 1
+                    || order_by.iter().any(|order| order.field.is_document_id())
+                    || aggregations > 0
+                {
+                    return Err(ParseError::InvalidCursor(format!(
+                        "cursor has {} values but there are {} ORDER BY fields",
+                        cursor_values,
+                        order_by.len()
+                    )));
+                }
+                let direction = order_by
+                    .last()
+                    .map(|order| order.direction)
+                    .unwrap_or(OrderDirection::Ascending);
+                order_by.push(OrderBy {
+                    field: FieldPath::document_id(),
+                    direction,
+                });
+            }
+            validate_document_id_cursors(&order_by, start_at.iter().chain(end_at.iter()))?;
+
+            if let Some(Limit::Last(_)) = limit {
+                if !ordered {
+                    return Err(ParseError::InvalidLimit(
+                        "LIMIT TO LAST requires ORDER BY clause".to_owned(),
+                    ));
+                }
+                if offset.is_some() {
+                    return Err(ParseError::InvalidLimit(
+                        "LIMIT TO LAST cannot be combined with OFFSET".to_owned(),
+                    ));
+                }
             }
-            _ => Err(ParseError::UnexpectedItem(parsed.as_str().to_string())),
-        }?;
 
-        Ok(select_stmt)
+            Ok(FireSQLSelect {
+                distinct,
+                projections,
+                collection,
+                collection_alias: resolver.joined_collections_alias(),
+                joins,
+                conditions,
+                client_conditions,
+                group_by,
+                having,
+                order_by,
+                start_at,
+                end_at,
+                limit,
+                offset,
+                set_operations: vec![],
+            })
+        }
+        _ => Err(ParseError::UnexpectedItem(parsed.as_str().to_string())),
+    }?;
+
+    Ok(select_stmt)
+}
+
+fn parse_having_or_expr(
+    or_expr: pest::iterators::Pair<'_, Rule>,
+    projections: &[SelectProjection],
+    group_by: &[FieldPath],
+    resolver: &PathResolver,
+) -> Result<HavingCondition, ParseError> {
+    use itertools::*;
+    let mut alternatives = or_expr
+        .into_inner()
+        .map(|and_expr| {
+            let mut conditions = and_expr
+                .into_inner()
+                .map(|term| parse_having_term(term, projections, group_by, resolver))
+                .process_results(|c| c.collect_vec())?;
+            if conditions.len() == 1 {
+                Ok(conditions.remove(0))
+            } else {
+                Ok(HavingCondition::And(conditions))
+            }
+        })
+        .process_results(|c| c.collect_vec())?;
+    if alternatives.len() == 1 {
+        Ok(alternatives.remove(0))
+    } else {
+        Ok(HavingCondition::Or(alternatives))
     }
 }
 
-fn parse_collection(tables: pest::iterators::Pair<'_, Rule>) -> Result<Collection, ParseError> {
-    let raw_path = tables.as_str();
-    let path = if tables.into_inner().len() % 2 != 1 {
-        Err(ParseError::InvalidCollectionPath(format!(
-            "Invalid collection path {}",
-            raw_path
-        )))
+fn parse_having_term(
+    term: pest::iterators::Pair<'_, Rule>,
+    projections: &[SelectProjection],
+    group_by: &[FieldPath],
+    resolver: &PathResolver,
+) -> Result<HavingCondition, ParseError> {
+    match term.as_rule() {
+        Rule::having_or_expr => parse_having_or_expr(term, projections, group_by, resolver),
+        Rule::having_not_expr => {
+            let term = term
+                .into_inner()
+                .next()
+                .expect("negated condition expected");
+            let condition = parse_having_term(term, projections, group_by, resolver)?;
+            Ok(HavingCondition::Not(Box::new(condition)))
+        }
+        _ => {
+            let mut comparison_inner = term.into_inner();
+            let operand = comparison_inner.next().expect("operand expected");
+            let operand = match operand.as_rule() {
+                Rule::aggregate_projection => HavingOperand::Aggregate(
+                    parse_aggregation(operand).map_field(|field| resolver.resolve(field)),
+                ),
+                _ => {
+                    let field = parse_field_path(operand);
+                    // projection aliases can be used in place of the projected expressions
+                    let aliased = projections.iter().find_map(|projection| match projection {
+                        SelectProjection::Property(path, Some(alias))
+                            if field.0 == [alias.as_str()] =>
+                        {
+                            Some(HavingOperand::Field(path.clone()))
+                        }
+                        SelectProjection::Aggregate(aggregation, Some(alias))
+                            if field.0 == [alias.as_str()] =>
+                        {
+                            Some(HavingOperand::Aggregate(aggregation.clone()))
+                        }
+                        _ => None,
+                    });
+                    let field = resolver.resolve(field);
+                    match aliased {
+                        Some(operand) => operand,
+                        None if group_by.contains(&field) => HavingOperand::Field(field),
+                        None => {
+                            return Err(ParseError::InvalidGrouping(format!(
+                                "{} is neither grouped nor aggregated",
+                                field
+                            )))
+                        }
+                    }
+                }
+            };
+            let operator = comparison_inner.next().expect("operator expected");
+            let value = parse_value(comparison_inner.next().expect("value expected"))?;
+            let operation = parse_compare_operation(operator.as_str(), value)?;
+            Ok(HavingCondition::Comparison(operand, operation))
+        }
+    }
+}
+
+fn parse_where(where_stmt: pest::iterators::Pair<'_, Rule>) -> Result<Vec<Condition>, ParseError> {
+    let or_expr = where_stmt.into_inner().next().expect("condition expected");
+    // top level AND is kept as a flat list of conditions
+    let condition = parse_or_expr(or_expr)?.with_null_checks();
+    validate_value_lists(&condition)?;
+    let conditions = match condition {
+        Condition::And(conditions) => conditions,
+        condition => vec![condition],
+    };
+    Ok(conditions)
+}
+
+fn validate_value_lists(condition: &Condition) -> Result<(), ParseError> {
+    match condition {
+        Condition::And(conditions) | Condition::Or(conditions) => {
+            conditions.iter().try_for_each(validate_value_lists)
+        }
+        Condition::Not(condition) | Condition::Dereference(_, condition) => {
+            validate_value_lists(condition)
+        }
+        Condition::Comparison(_, CompareOperations::InSubquery(select)) => select
+            .conditions
+            .iter()
+            .chain(select.client_conditions.iter())
+            .try_for_each(validate_value_lists),
+        Condition::Comparison(field, CompareOperations::In(values))
+            if values.len() > IN_VALUES_LIMIT =>
+        {
+            Err(ParseError::TooManyValues(format!(
+                "{} IN list has {} values, at most {} allowed",
+                field,
+                values.len(),
+                IN_VALUES_LIMIT
+            )))
+        }
+        Condition::Comparison(field, CompareOperations::NotIn(values))
+            if values.len() > NOT_IN_VALUES_LIMIT =>
+        {
+            Err(ParseError::TooManyValues(format!(
+                "{} NOT IN list has {} values, at most {} allowed",
+                field,
+                values.len(),
+                NOT_IN_VALUES_LIMIT
+            )))
+        }
+        Condition::Comparison(field, CompareOperations::ArrayContainsAny(values))
+            if values.len() > ARRAY_CONTAINS_ANY_VALUES_LIMIT =>
+        {
+            Err(ParseError::TooManyValues(format!(
+                "{} ARRAY_CONTAINS_ANY list has {} values, at most {} allowed",
+                field,
+                values.len(),
+                ARRAY_CONTAINS_ANY_VALUES_LIMIT
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn parse_or_expr(or_expr: pest::iterators::Pair<'_, Rule>) -> Result<Condition, ParseError> {
+    use itertools::*;
+    let mut alternatives = or_expr
+        .into_inner()
+        .map(parse_and_expr)
+        .process_results(|c| c.collect_vec())?;
+    if alternatives.len() == 1 {
+        Ok(alternatives.remove(0))
+    } else {
+        Ok(Condition::Or(alternatives))
+    }
+}
+
+fn parse_and_expr(and_expr: pest::iterators::Pair<'_, Rule>) -> Result<Condition, ParseError> {
+    use itertools::*;
+    let conditions = and_expr
+        .into_inner()
+        .map(|term| match term.as_rule() {
+            Rule::or_expr => parse_or_expr(term),
+            Rule::not_expr => parse_not_expr(term),
+            _ => parse_condition(term),
+        })
+        .process_results(|c| c.collect_vec())?;
+    // nested AND groups are flattened, as (a AND b) AND c equals a AND b AND c
+    let mut conditions = conditions
+        .into_iter()
+        .flat_map(|condition| match condition {
+            Condition::And(conditions) => conditions,
+            condition => vec![condition],
+        })
+        .collect_vec();
+    if conditions.len() == 1 {
+        Ok(conditions.remove(0))
     } else {
-        Ok(raw_path.trim().to_owned())
+        Ok(Condition::And(conditions))
+    }
+}
+
+fn parse_not_expr(not_expr: pest::iterators::Pair<'_, Rule>) -> Result<Condition, ParseError> {
+    let source = not_expr.as_str().to_owned();
+    let term = not_expr
+        .into_inner()
+        .next()
+        .expect("negated condition expected");
+    let condition = match term.as_rule() {
+        Rule::or_expr => parse_or_expr(term),
+        Rule::not_expr => parse_not_expr(term),
+        _ => parse_condition(term),
     }?;
-    let collection = Collection { path };
-    Ok(collection)
+    negate(condition).ok_or(ParseError::UnsupportedNegation(source))
+}
+
+/// Rewrites negated condition into the form that can be pushed to Firestore.
+/// Returns `None` when there is no such form.
+fn negate(condition: Condition) -> Option<Condition> {
+    match condition {
+        Condition::And(conditions) => conditions
+            .into_iter()
+            .map(negate)
+            .collect::<Option<Vec<_>>>()
+            .map(Condition::Or),
+        Condition::Or(conditions) => conditions
+            .into_iter()
+            .map(negate)
+            .collect::<Option<Vec<_>>>()
+            .map(Condition::And),
+        Condition::Not(condition) => Some(*condition),
+        Condition::Dereference(reference, condition) => Some(Condition::Dereference(
+            reference,
+            Box::new(negate(*condition)?),
+        )),
+        Condition::IsNull(field) => Some(Condition::Not(Box::new(Condition::IsNull(field)))),
+        Condition::Comparison(field, operation) => {
+            let operation = match operation {
+                CompareOperations::Equal(value) => Some(CompareOperations::NotEqual(value)),
+                CompareOperations::NotEqual(value) => Some(CompareOperations::Equal(value)),
+                // range filters match only values of the same type, so their negation
+                // cannot be another range filter
+                CompareOperations::GreaterThan(_)
+                | CompareOperations::GreaterThanOrEqual(_)
+                | CompareOperations::LessThan(_)
+                | CompareOperations::LessThanOrEqual(_) => None,
+                CompareOperations::In(values) => Some(CompareOperations::NotIn(values)),
+                CompareOperations::NotIn(values) => Some(CompareOperations::In(values)),
+                CompareOperations::ArrayContains(_)
+                | CompareOperations::ArrayContainsAny(_)
+                | CompareOperations::InSubquery(_) => None,
+            }?;
+            Some(Condition::Comparison(field, operation))
+        }
+    }
+}
+
+fn parse_condition(condition: pest::iterators::Pair<'_, Rule>) -> Result<Condition, ParseError> {
+    match condition.as_rule() {
+        Rule::is_null => Ok(field_condition(
+            condition.into_inner().next().expect("property expected"),
+            Condition::IsNull,
+        )),
+        Rule::is_not_null => Ok(field_condition(
+            condition.into_inner().next().expect("property expected"),
+            |field| Condition::Not(Box::new(Condition::IsNull(field))),
+        )),
+        Rule::comparison => {
+            let mut comparison_inner = condition.into_inner();
+            let property_name = comparison_inner.next().expect("property expected");
+            let operator = comparison_inner.next().expect("operator expected");
+            let value = comparison_inner.next().expect("value expected");
+            let value = parse_value(value)?;
+            let operation = parse_compare_operation(operator.as_str(), value)?;
+            Ok(field_condition(property_name, |field| {
+                Condition::Comparison(field, operation)
+            }))
+        }
+        Rule::in_list | Rule::not_in_list => {
+            let rule = condition.as_rule();
+            let mut in_list_inner = condition.into_inner();
+            let property_name = in_list_inner.next().expect("property expected");
+            let values = in_list_inner.next().expect("values expected");
+            let values = parse_value_list(values)?;
+            let operation = match rule {
+                Rule::in_list => CompareOperations::In(values),
+                _ => CompareOperations::NotIn(values),
+            };
+            Ok(field_condition(property_name, |field| {
+                Condition::Comparison(field, operation)
+            }))
+        }
+        Rule::in_subquery => {
+            let mut in_subquery_inner = condition.into_inner();
+            let property_name = in_subquery_inner.next().expect("property expected");
+            let select = parse_select(in_subquery_inner.next().expect("subquery expected"))?;
+            validate_subquery(&select)?;
+            Ok(Condition::Comparison(
+                parse_field_path(property_name),
+                CompareOperations::InSubquery(Box::new(select)),
+            ))
+        }
+        Rule::array_contains => {
+            let mut array_contains_inner = condition.into_inner();
+            let property_name = array_contains_inner.next().expect("property expected");
+            let value = array_contains_inner.next().expect("value expected");
+            let value = parse_value(value)?;
+            Ok(field_condition(property_name, |field| {
+                Condition::Comparison(field, CompareOperations::ArrayContains(value))
+            }))
+        }
+        Rule::array_contains_any => {
+            let mut array_contains_inner = condition.into_inner();
+            let property_name = array_contains_inner.next().expect("property expected");
+            let values = array_contains_inner.next().expect("values expected");
+            let values = parse_value_list(values)?;
+            Ok(field_condition(property_name, |field| {
+                Condition::Comparison(field, CompareOperations::ArrayContainsAny(values))
+            }))
+        }
+        rule => Err(ParseError::UnexpectedItem(format!(
+            "rule {:?} - {}",
+            rule,
+            condition.as_str()
+        ))),
+    }
+}
+
+/// Cursor values of the document id order are document ids or references.
+fn validate_document_id_cursors<'a>(
+    order_by: &[OrderBy],
+    cursors: impl Iterator<Item = &'a Cursor>,
+) -> Result<(), ParseError> {
+    for cursor in cursors {
+        for (value, order) in cursor.values.iter().zip(order_by) {
+            if order.field.is_document_id()
+                && !matches!(
+                    value,
+                    Value::String(_) | Value::Reference(_) | Value::Parameter(_)
+                )
+            {
+                return Err(ParseError::InvalidCursor(
+                    "document id cursor value should be a string or a reference".to_owned(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Values of the subquery are compared with the field, so it selects a single column
+/// of documents.
+fn validate_subquery(select: &FireSQLSelect) -> Result<(), ParseError> {
+    if !select.group_by.is_empty() || select.having.is_some() {
+        return Err(ParseError::InvalidSubquery(
+            "subquery cannot group documents".to_owned(),
+        ));
+    }
+    match select.projections.as_slice() {
+        [SelectProjection::ObjectId(_)
+        | SelectProjection::Property(..)
+        | SelectProjection::Dereference(..)] => Ok(()),
+        _ => Err(ParseError::InvalidSubquery(
+            "subquery should select a single document field or :id".to_owned(),
+        )),
+    }
+}
+
+/// Condition on the field, which may be a field of a referenced document (`author->name`).
+fn field_condition(
+    field: pest::iterators::Pair<'_, Rule>,
+    condition: impl FnOnce(FieldPath) -> Condition,
+) -> Condition {
+    let mut references = parse_dereference(field);
+    let field = references.pop().expect("field path expected");
+    references
+        .into_iter()
+        .rev()
+        .fold(condition(field), |condition, reference| {
+            Condition::Dereference(reference, Box::new(condition))
+        })
+}
+
+/// Fields of the dereference chain - every field but the last one holds a document reference.
+fn parse_dereference(dereference: pest::iterators::Pair<'_, Rule>) -> Vec<FieldPath> {
+    match dereference.as_rule() {
+        Rule::dereference => dereference.into_inner().map(parse_field_path).collect(),
+        _ => vec![parse_field_path(dereference)],
+    }
+}
+
+fn parse_compare_operation(operator: &str, value: Value) -> Result<CompareOperations, ParseError> {
+    match operator {
+        "=" => Ok(CompareOperations::Equal(value)),
+        "!=" => Ok(CompareOperations::NotEqual(value)),
+        ">" => Ok(CompareOperations::GreaterThan(value)),
+        ">=" => Ok(CompareOperations::GreaterThanOrEqual(value)),
+        "<" => Ok(CompareOperations::LessThan(value)),
+        "<=" => Ok(CompareOperations::LessThanOrEqual(value)),
+        _ => Err(ParseError::UnexpectedItem(operator.to_owned())),
+    }
+}
+
+fn parse_field_path(field_path: pest::iterators::Pair<'_, Rule>) -> FieldPath {
+    FieldPath(field_path.into_inner().map(parse_field_segment).collect())
+}
+
+fn parse_field_segment(segment: pest::iterators::Pair<'_, Rule>) -> String {
+    match segment.as_rule() {
+        Rule::quoted_field_segment => {
+            let inner = segment
+                .into_inner()
+                .next()
+                .expect("quoted segment expected");
+            let mut unescaped = String::new();
+            let mut chars = inner.as_str().chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unescaped.extend(chars.next()),
+                    c => unescaped.push(c),
+                }
+            }
+            unescaped
+        }
+        _ => segment.as_str().to_owned(),
+    }
+}
+
+fn parse_value_list(values: pest::iterators::Pair<'_, Rule>) -> Result<Vec<Value>, ParseError> {
+    use itertools::*;
+    values
+        .into_inner()
+        .map(parse_value)
+        .process_results(|v| v.collect_vec())
+}
+
+fn parse_value(value: pest::iterators::Pair<'_, Rule>) -> Result<Value, ParseError> {
+    let inner_value = value.into_inner().next().expect("inner value expected");
+    match inner_value.as_rule() {
+        Rule::number => parse_number(inner_value.as_str()),
+        Rule::string => Ok(Value::String(parse_string(inner_value))),
+        Rule::reference => parse_reference(inner_value),
+        Rule::bool => Ok(Value::Bool(
+            inner_value.as_str().eq_ignore_ascii_case("true"),
+        )),
+        Rule::null => Ok(Value::Null),
+        Rule::timestamp => {
+            let timestamp =
+                parse_string(inner_value.into_inner().next().expect("timestamp expected"));
+            timestamp
+                .parse::<gcloud_sdk::prost_types::Timestamp>()
+                .map(|parsed| Value::Timestamp(parsed.seconds, parsed.nanos))
+                .map_err(|_| ParseError::InvalidTimestamp(timestamp))
+        }
+        Rule::geopoint => {
+            let source = inner_value.as_str().to_owned();
+            let mut geopoint_inner = inner_value.into_inner();
+            let mut coordinate = || match parse_number(
+                geopoint_inner.next().expect("coordinate expected").as_str(),
+            )? {
+                Value::Integer(coordinate) => Ok(coordinate as f64),
+                Value::Double(coordinate) => Ok(coordinate),
+                _ => unreachable!(),
+            };
+            let (latitude, longitude) = (coordinate()?, coordinate()?);
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err(ParseError::InvalidGeoPoint(source));
+            }
+            Ok(Value::GeoPoint(latitude, longitude))
+        }
+        Rule::bytes => {
+            let hex_digits = inner_value
+                .into_inner()
+                .next()
+                .and_then(|hex_literal| hex_literal.into_inner().next())
+                .expect("hex digits expected")
+                .as_str();
+            let bytes = (0..hex_digits.len())
+                .step_by(2)
+                .map(|index| u8::from_str_radix(&hex_digits[index..index + 2], 16))
+                .collect::<Result<Vec<_>, _>>()
+                .expect("hex digits are validated by grammar");
+            Ok(Value::Bytes(bytes))
+        }
+        Rule::array => {
+            use itertools::*;
+            let values = inner_value
+                .into_inner()
+                .map(parse_value)
+                .process_results(|v| v.collect_vec())?;
+            Ok(Value::Array(values))
+        }
+        Rule::map => {
+            let mut entries = BTreeMap::new();
+            for entry in inner_value.into_inner() {
+                let mut entry_inner = entry.into_inner();
+                let key = parse_string(entry_inner.next().expect("map key expected"));
+                let value = parse_value(entry_inner.next().expect("map value expected"))?;
+                entries.insert(key, value);
+            }
+            Ok(Value::Map(entries))
+        }
+        Rule::parameter => {
+            let parameter = inner_value.into_inner().next().expect("parameter expected");
+            match parameter.as_rule() {
+                Rule::positional_parameter => parameter
+                    .as_str()
+                    .parse()
+                    .map(|position| Value::Parameter(Parameter::Positional(position)))
+                    .map_err(|_| ParseError::UnexpectedItem(parameter.as_str().to_owned())),
+                _ => Ok(Value::Parameter(Parameter::Named(
+                    parameter.as_str().to_owned(),
+                ))),
+            }
+        }
+
+        rule => Err(ParseError::UnexpectedItem(format!(
+            "rule {:?} - {}",
+            rule,
+            inner_value.as_str()
+        ))),
+    }
+}
+
+fn parse_string(string: pest::iterators::Pair<'_, Rule>) -> String {
+    let inner_string = string.into_inner().next().expect("inner_string expected");
+    match inner_string.as_rule() {
+        Rule::single_quoted_inner_string => inner_string.as_str().replace("''", "'"),
+        _ => unescape(inner_string.as_str()),
+    }
+}
+
+/// Decodes escape sequences of double quoted string. Escapes are validated by grammar,
+/// invalid UTF-16 surrogates are decoded as replacement character.
+fn unescape(escaped: &str) -> String {
+    let mut unescaped = String::with_capacity(escaped.len());
+    let mut utf16_units = vec![];
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next().expect("escaped character expected") {
+                'u' => {
+                    let code = chars.by_ref().take(4).collect::<String>();
+                    utf16_units.push(u16::from_str_radix(&code, 16).expect("hex digits expected"));
+                    continue;
+                }
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                c => c,
+            },
+            c => c,
+        };
+        unescaped.extend(
+            char::decode_utf16(utf16_units.drain(..))
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)),
+        );
+        unescaped.push(c);
+    }
+    unescaped
+        .extend(char::decode_utf16(utf16_units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+    unescaped
+}
+
+/// Reference is either a document path relative to the database
+/// or an absolute `projects/{project}/databases/{database}/documents/{path}` name.
+fn parse_reference(reference: pest::iterators::Pair<'_, Rule>) -> Result<Value, ParseError> {
+    use itertools::*;
+    let path = reference
+        .into_inner()
+        .next()
+        .expect("reference path expected");
+    let path = match path.as_rule() {
+        Rule::string => parse_string(path),
+        _ => path.into_inner().map(|segment| segment.as_str()).join("/"),
+    };
+    validate_reference(&path)?;
+    Ok(Value::Reference(path))
+}
+
+/// Reference should point to a document, so its path has an even number of segments.
+fn validate_reference(path: &str) -> Result<(), ParseError> {
+    use itertools::*;
+    let segments = path.split('/').collect_vec();
+    let document_segments = match is_document_name(path) {
+        true => &segments[5..],
+        false => &segments[..],
+    };
+    if document_segments.is_empty()
+        || document_segments.len() % 2 != 0
+        || document_segments.iter().any(|segment| segment.is_empty())
+    {
+        return Err(ParseError::InvalidReference(path.to_owned()));
+    }
+    Ok(())
+}
+
+/// Whether the reference path is a full document name (`projects/*/databases/*/documents/...`)
+/// rather than a path relative to the database root.
+pub(crate) fn is_document_name(path: &str) -> bool {
+    matches!(
+        path.split('/').collect::<Vec<_>>().as_slice(),
+        ["projects", _, "databases", _, "documents", ..]
+    )
+}
+
+/// Literals with fraction or exponent are doubles, all other are integers.
+fn parse_number(number: &str) -> Result<Value, ParseError> {
+    if number.contains(['.', 'e', 'E']) {
+        number
+            .parse()
+            .map(Value::Double)
+            .map_err(|_| ParseError::InvalidNumber(number.to_owned()))
+    } else {
+        number
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| ParseError::InvalidNumber(number.to_owned()))
+    }
+}
+
+fn parse_order_by(order_by_stmt: pest::iterators::Pair<'_, Rule>) -> Vec<OrderBy> {
+    order_by_stmt
+        .into_inner()
+        .map(|order_by_field| {
+            let mut order_by_inner = order_by_field.into_inner();
+            let field = order_by_inner.next().expect("order field expected");
+            let field = match field.as_rule() {
+                Rule::id_projection => FieldPath::document_id(),
+                _ => parse_field_path(field),
+            };
+            let direction = match order_by_inner.next() {
+                Some(direction) if direction.as_str().eq_ignore_ascii_case("desc") => {
+                    OrderDirection::Descending
+                }
+                _ => OrderDirection::Ascending,
+            };
+            OrderBy { field, direction }
+        })
+        .collect()
+}
+
+fn parse_cursor(cursor: pest::iterators::Pair<'_, Rule>) -> Result<Cursor, ParseError> {
+    let mut cursor_inner = cursor.into_inner();
+    let position = cursor_inner.next().expect("cursor position expected");
+    let inclusive = matches!(position.as_rule(), Rule::start_at | Rule::end_at);
+    let values = cursor_inner.next().expect("cursor values expected");
+    let values = parse_value_list(values)?;
+    Ok(Cursor { values, inclusive })
+}
+
+fn parse_limit(limit_stmt: pest::iterators::Pair<'_, Rule>) -> Result<Limit, ParseError> {
+    let mut limit_inner = limit_stmt.into_inner();
+    let next = limit_inner.next().expect("limit expected");
+    match next.as_rule() {
+        Rule::limit_to_last => {
+            let integer = limit_inner.next().expect("limit expected");
+            Ok(Limit::Last(parse_integer(integer)?))
+        }
+        _ => Ok(Limit::First(parse_integer(next)?)),
+    }
+}
+
+fn parse_integer(integer: pest::iterators::Pair<'_, Rule>) -> Result<u32, ParseError> {
+    integer
+        .as_str()
+        .parse()
+        .map_err(|_| ParseError::InvalidLimit(format!("{} is out of range", integer.as_str())))
+}
+
+fn parse_from_item(
+    from_item: pest::iterators::Pair<'_, Rule>,
+) -> Result<(Collection, Option<String>), ParseError> {
+    let mut from_inner = from_item.into_inner();
+    let collection = parse_collection(from_inner.next().expect("collection expected"))?;
+    let alias = from_inner.next().map(|alias| alias.as_str().to_owned());
+    Ok((collection, alias))
+}
+
+/// Parses the join with the alias of joined collection and unresolved fields of the join condition.
+fn parse_join(join: pest::iterators::Pair<'_, Rule>) -> Result<UnresolvedJoin, ParseError> {
+    let mut join_inner = join.into_inner().peekable();
+    let kind = match join_inner.next_if(|pair| pair.as_rule() == Rule::join_kind) {
+        Some(kind) if kind.as_str().to_ascii_uppercase().starts_with("LEFT") => JoinKind::Left,
+        _ => JoinKind::Inner,
+    };
+    let (collection, alias) = parse_from_item(join_inner.next().expect("joined collection"))?;
+    let alias = alias.unwrap_or_else(|| default_alias(&collection));
+    let mut on_fields = join_inner.map(|field| match field.as_rule() {
+        Rule::qualified_id => parse_qualified_id(field),
+        _ => parse_field_path(field),
+    });
+    let on = (
+        on_fields.next().expect("join field expected"),
+        on_fields.next().expect("join field expected"),
+    );
+    Ok(UnresolvedJoin {
+        kind,
+        collection,
+        alias,
+        on,
+    })
+}
+
+/// `alias.:id` - document id of one of joined collections.
+fn parse_qualified_id(qualified_id: pest::iterators::Pair<'_, Rule>) -> FieldPath {
+    let alias = qualified_id
+        .into_inner()
+        .next()
+        .expect("collection alias expected");
+    FieldPath(vec![
+        alias.as_str().to_owned(),
+        DOCUMENT_ID_FIELD.to_owned(),
+    ])
+}
+
+/// Collections without explicit alias are referred to by their name.
+fn default_alias(collection: &Collection) -> String {
+    collection
+        .path
+        .rsplit('/')
+        .next()
+        .expect("collection name")
+        .to_owned()
+}
+
+struct UnresolvedJoin {
+    kind: JoinKind,
+    collection: Collection,
+    alias: String,
+    on: (FieldPath, FieldPath),
+}
+
+/// Resolves field paths of statements with collection aliases. In statements with joins
+/// every path starts with the alias of its collection - the paths without alias refer
+/// to the main collection. Without joins the alias of the main collection is removed.
+struct PathResolver {
+    main: Option<String>,
+    joined: Vec<String>,
+}
+
+impl PathResolver {
+    fn new(
+        collection: &Collection,
+        alias: Option<String>,
+        joins: &[UnresolvedJoin],
+    ) -> Result<Self, ParseError> {
+        let main = match joins.is_empty() {
+            true => alias,
+            false => Some(alias.unwrap_or_else(|| default_alias(collection))),
+        };
+        let joined = joins.iter().map(|join| join.alias.clone()).collect_vec();
+        if let Some(duplicate) = main.iter().chain(joined.iter()).duplicates().next() {
+            return Err(ParseError::InvalidJoin(format!(
+                "collection alias {} is used more than once",
+                duplicate
+            )));
+        }
+        Ok(Self { main, joined })
+    }
+
+    fn is_alias(&self, segment: &str) -> bool {
+        self.main.as_deref() == Some(segment) || self.joined.iter().any(|alias| alias == segment)
+    }
+
+    fn resolve(&self, path: FieldPath) -> FieldPath {
+        let Some(main) = self.main.as_ref() else {
+            return path;
+        };
+        let qualified = path.0.len() > 1 && self.is_alias(&path.0[0]);
+        match (self.joined.is_empty(), qualified) {
+            (true, true) => FieldPath(path.0[1..].to_vec()),
+            (false, false) => FieldPath(std::iter::once(main.clone()).chain(path.0).collect()),
+            _ => path,
+        }
+    }
+
+    /// Path in the documents of the main collection, `None` if the path refers
+    /// to a joined collection.
+    fn main_collection_path(&self, path: FieldPath) -> Option<FieldPath> {
+        if self.joined.is_empty() {
+            return Some(path);
+        }
+        (self.main.as_ref() == path.0.first()).then(|| FieldPath(path.0[1..].to_vec()))
+    }
+
+    fn joined_collections_alias(&self) -> Option<String> {
+        self.main.clone().filter(|_| !self.joined.is_empty())
+    }
+
+    fn resolve_projection(
+        &self,
+        projection: SelectProjection,
+    ) -> Result<SelectProjection, ParseError> {
+        match projection {
+            SelectProjection::Property(field, alias) => {
+                let field = self.resolve(field);
+                if field.is_document_id() {
+                    return Ok(SelectProjection::ObjectId(alias));
+                }
+                // `alias.:id` refers to one of the collections
+                if field.0.last().map(String::as_str) == Some(DOCUMENT_ID_FIELD)
+                    && (field.0.len() != 2 || !self.is_alias(&field.0[0]))
+                {
+                    return Err(ParseError::InvalidJoin(format!(
+                        "unknown collection alias in {}",
+                        field
+                    )));
+                }
+                Ok(SelectProjection::Property(field, alias))
+            }
+            SelectProjection::Dereference(mut paths, alias) => {
+                // the following fields are in the referenced documents
+                paths[0] = self.resolve(paths[0].clone());
+                Ok(SelectProjection::Dereference(paths, alias))
+            }
+            SelectProjection::Aggregate(aggregation, alias) => Ok(SelectProjection::Aggregate(
+                aggregation.map_field(|field| self.resolve(field)),
+                alias,
+            )),
+            projection => Ok(projection),
+        }
+    }
+
+    /// One side of the join condition refers to the joined collection, the other one
+    /// to the main collection or one of the collections joined before.
+    fn resolve_join(&self, index: usize, join: UnresolvedJoin) -> Result<Join, ParseError> {
+        let (a, b) = (self.resolve(join.on.0), self.resolve(join.on.1));
+        let (left, right) = match (a.0[0] == join.alias, b.0[0] == join.alias) {
+            (false, true) => (a, b),
+            (true, false) => (b, a),
+            _ => {
+                return Err(ParseError::InvalidJoin(format!(
+                    "condition of {} join should compare fields of two collections",
+                    join.alias
+                )))
+            }
+        };
+        let available = self.main.iter().chain(self.joined[..index].iter());
+        if !available.into_iter().any(|alias| *alias == left.0[0]) {
+            return Err(ParseError::InvalidJoin(format!(
+                "{} does not refer to any of previous collections",
+                left
+            )));
+        }
+        let right = FieldPath(right.0[1..].to_vec());
+        if right.is_document_id() && join.collection.all_descendants {
+            return Err(ParseError::InvalidJoin(format!(
+                "collection group {} can be joined only on document fields",
+                join.alias
+            )));
+        }
+        Ok(Join {
+            kind: join.kind,
+            collection: join.collection,
+            alias: join.alias,
+            left,
+            right,
+        })
+    }
+}
+
+fn parse_collection(tables: pest::iterators::Pair<'_, Rule>) -> Result<Collection, ParseError> {
+    let raw_path = tables.as_str();
+    let tables = tables.into_inner().next().expect("collection expected");
+    match tables.as_rule() {
+        Rule::collection_group => {
+            let name = tables
+                .into_inner()
+                .next()
+                .expect("collection name expected");
+            Ok(Collection {
+                path: name.as_str().to_owned(),
+                all_descendants: true,
+            })
+        }
+        Rule::scoped_collection_group => {
+            let mut group_inner = tables.into_inner();
+            let parent = group_inner.next().expect("parent document expected");
+            let name = group_inner.next().expect("collection name expected");
+            let parent_path = parent.as_str().trim().to_owned();
+            if parent.into_inner().len() % 2 != 0 {
+                return Err(ParseError::InvalidCollectionPath(format!(
+                    "Invalid parent document path {}",
+                    parent_path
+                )));
+            }
+            Ok(Collection {
+                path: format!("{}/{}", parent_path, name.as_str()),
+                all_descendants: true,
+            })
+        }
+        _ => {
+            let path = if tables.into_inner().len() % 2 != 1 {
+                Err(ParseError::InvalidCollectionPath(format!(
+                    "Invalid collection path {}",
+                    raw_path
+                )))
+            } else {
+                Ok(raw_path.trim().to_owned())
+            }?;
+            Ok(Collection {
+                path,
+                all_descendants: false,
+            })
+        }
+    }
 }
 
 fn parse_projections(projections: pest::iterators::Pair<'_, Rule>) -> Vec<SelectProjection> {
     let projections = projections
         .into_inner()
         .map(|proj| {
-            let proj = proj
-                .into_inner()
-                .next()
-                .expect("select projection expected");
+            let mut proj_inner = proj.into_inner();
+            let proj = proj_inner.next().expect("select projection expected");
+            let alias = proj_inner.next().map(parse_alias);
             match proj.as_rule() {
-                Rule::id_projection => SelectProjection::ObjectId,
+                Rule::id_projection => SelectProjection::ObjectId(alias),
+                Rule::qualified_id => SelectProjection::Property(parse_qualified_id(proj), alias),
                 Rule::object_projection => SelectProjection::Object,
-                Rule::ident => SelectProjection::Property(proj.as_str().to_owned()),
+                Rule::field_path => SelectProjection::Property(parse_field_path(proj), alias),
+                Rule::dereference => SelectProjection::Dereference(parse_dereference(proj), alias),
+                Rule::aggregate_projection => {
+                    SelectProjection::Aggregate(parse_aggregation(proj), alias)
+                }
                 _ => unreachable!(),
             }
         })
@@ -140,11 +1289,52 @@ fn parse_projections(projections: pest::iterators::Pair<'_, Rule>) -> Vec<Select
     projections
 }
 
+fn parse_aggregation(aggregation: pest::iterators::Pair<'_, Rule>) -> Aggregation {
+    let mut aggregation_inner = aggregation.into_inner();
+    let function = aggregation_inner
+        .next()
+        .expect("aggregate function expected");
+    if function.as_rule() == Rule::count_all {
+        return Aggregation::CountAll;
+    }
+    let field = parse_field_path(aggregation_inner.next().expect("aggregated field expected"));
+    match function.as_str().to_ascii_uppercase().as_str() {
+        "COUNT" => Aggregation::Count(field),
+        "SUM" => Aggregation::Sum(field),
+        "AVG" => Aggregation::Avg(field),
+        "MIN" => Aggregation::Min(field),
+        _ => Aggregation::Max(field),
+    }
+}
+
+fn parse_alias(alias: pest::iterators::Pair<'_, Rule>) -> String {
+    let alias_name = alias.into_inner().next().expect("alias name expected");
+    let segment = alias_name
+        .into_inner()
+        .next()
+        .expect("alias segment expected");
+    parse_field_segment(segment)
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ParseError {
     GrammarError(Box<pest::error::Error<Rule>>),
     UnexpectedItem(String),
     InvalidCollectionPath(String),
+    UnsupportedNegation(String),
+    TooManyValues(String),
+    InvalidLimit(String),
+    InvalidCursor(String),
+    InvalidNumber(String),
+    InvalidReference(String),
+    InvalidTimestamp(String),
+    InvalidGeoPoint(String),
+    InvalidProjection(String),
+    InvalidGrouping(String),
+    InvalidJoin(String),
+    InvalidSubquery(String),
+    InvalidSetOperation(String),
+    UnknownParameter(String),
 }
 
 impl core::fmt::Display for ParseError {
@@ -155,6 +1345,30 @@ impl core::fmt::Display for ParseError {
             ParseError::InvalidCollectionPath(path) => {
                 write!(f, "Invalid collection path: {}", path)
             }
+            ParseError::UnsupportedNegation(condition) => {
+                write!(f, "Condition cannot be negated: {}", condition)
+            }
+            ParseError::TooManyValues(details) => write!(f, "Too many values: {}", details),
+            ParseError::InvalidLimit(details) => write!(f, "Invalid limit: {}", details),
+            ParseError::InvalidCursor(details) => write!(f, "Invalid cursor: {}", details),
+            ParseError::InvalidNumber(number) => write!(f, "Invalid number: {}", number),
+            ParseError::InvalidReference(path) => {
+                write!(f, "Invalid document reference: {}", path)
+            }
+            ParseError::InvalidTimestamp(timestamp) => {
+                write!(f, "Invalid timestamp: {}", timestamp)
+            }
+            ParseError::InvalidGeoPoint(geopoint) => write!(f, "Invalid geo point: {}", geopoint),
+            ParseError::InvalidProjection(details) => write!(f, "Invalid projection: {}", details),
+            ParseError::InvalidGrouping(details) => write!(f, "Invalid grouping: {}", details),
+            ParseError::InvalidJoin(details) => write!(f, "Invalid join: {}", details),
+            ParseError::InvalidSubquery(details) => write!(f, "Invalid subquery: {}", details),
+            ParseError::InvalidSetOperation(details) => {
+                write!(f, "Invalid set operation: {}", details)
+            }
+            ParseError::UnknownParameter(parameter) => {
+                write!(f, "Unknown parameter: {}", parameter)
+            }
         }
     }
 }
@@ -169,44 +1383,750 @@ impl std::error::Error for ParseError {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FireSQLSelect {
+    /// Duplicated rows are removed after the documents are fetched.
+    pub(super) distinct: bool,
     pub(super) projections: Vec<SelectProjection>,
     pub(super) collection: Collection,
+    /// Alias of the main collection in statements with joins.
+    pub(super) collection_alias: Option<String>,
+    /// Collections joined on the client side. Field paths of statements with joins
+    /// start with the alias of their collection.
+    pub(super) joins: Vec<Join>,
+    /// Conditions pushed to Firestore query of the main collection.
     pub(super) conditions: Vec<Condition>,
+    /// Conditions on joined collections and referenced documents checked
+    /// on the client side.
+    pub(super) client_conditions: Vec<Condition>,
+    /// Fields the documents are grouped by after they are fetched.
+    pub(super) group_by: Vec<FieldPath>,
+    pub(super) having: Option<HavingCondition>,
+    pub(super) order_by: Vec<OrderBy>,
+    pub(super) start_at: Option<Cursor>,
+    pub(super) end_at: Option<Cursor>,
+    pub(super) limit: Option<Limit>,
+    pub(super) offset: Option<u32>,
+    /// Statements combined with this one by UNION, INTERSECT and EXCEPT, in order.
+    /// Every statement is executed with its own clauses, ORDER BY, LIMIT and OFFSET
+    /// of the last one are accepted only in parentheses.
+    pub(super) set_operations: Vec<(SetOperator, FireSQLSelect)>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum SelectProjection {
-    ObjectId,
-    Object,
-    Property(String),
-}
+impl FireSQLSelect {
+    /// Binds the value to all occurrences of the parameter in the statement.
+    /// Positional parameters (`$1`, `$2`, ...) are numbered from 1.
+    /// Bound values are validated the same way as values written in the statement.
+    pub fn bind(
+        mut self,
+        parameter: impl Into<Parameter>,
+        value: impl Into<Value>,
+    ) -> Result<Self, ParseError> {
+        let parameter = parameter.into();
+        if !self.unbound_parameters().contains(&parameter) {
+            return Err(ParseError::UnknownParameter(parameter.to_string()));
+        }
+        let parameter = Value::Parameter(parameter);
+        let value = value.into();
+        self.visit_values_mut(&mut |v| {
+            if *v == parameter {
+                *v = value.clone();
+            }
+        });
+        self.rewrite_null_comparisons();
+        self.validate_values()?;
+        Ok(self)
+    }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Collection {
-    pub(super) path: String,
-}
+    /// Validates values of the statement, including the values bound to its parameters.
+    fn validate_values(&self) -> Result<(), ParseError> {
+        let mut references = vec![];
+        self.visit_values(&mut |value| {
+            if let Value::Reference(path) = value {
+                references.push(path);
+            }
+        });
+        for path in references {
+            validate_reference(path)?;
+        }
+        for condition in self.conditions.iter().chain(self.client_conditions.iter()) {
+            validate_value_lists(condition)?;
+        }
+        validate_document_id_cursors(
+            &self.order_by,
+            self.start_at.iter().chain(self.end_at.iter()),
+        )?;
+        for (_, select) in self.set_operations.iter() {
+            select.validate_values()?;
+        }
+        Ok(())
+    }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum Condition {
-    Not(Box<Condition>),
-    IsNull(String),
-    Comparison(String, CompareOperations),
-}
+    /// Rewrites comparisons with NULL, which may appear only after binding, into
+    /// null checks.
+    fn rewrite_null_comparisons(&mut self) {
+        for conditions in [&mut self.conditions, &mut self.client_conditions] {
+            *conditions = std::mem::take(conditions)
+                .into_iter()
+                .map(Condition::with_null_checks)
+                .collect();
+        }
+        for (_, select) in self.set_operations.iter_mut() {
+            select.rewrite_null_comparisons();
+        }
+    }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum CompareOperations {
-    Equal(Value),
+    /// Parameters of the statement that have no value bound yet.
+    pub fn unbound_parameters(&self) -> Vec<Parameter> {
+        let mut parameters = vec![];
+        self.visit_values(&mut |value| {
+            if let Value::Parameter(parameter) = value {
+                if !parameters.contains(parameter) {
+                    parameters.push(parameter.clone());
+                }
+            }
+        });
+        parameters
+    }
+
+    /// Calls the visitor for every value of the statement, including values nested
+    /// in arrays and maps.
+    pub(crate) fn visit_values<'a>(&'a self, visitor: &mut impl FnMut(&'a Value)) {
+        for condition in self.conditions.iter() {
+            condition.visit_values(visitor);
+        }
+        for condition in self.client_conditions.iter() {
+            condition.visit_values(visitor);
+        }
+        if let Some(having) = self.having.as_ref() {
+            having.visit_values(visitor);
+        }
+        for cursor in self.start_at.iter().chain(self.end_at.iter()) {
+            for value in cursor.values.iter() {
+                value.visit_values(visitor);
+            }
+        }
+        for (_, select) in self.set_operations.iter() {
+            select.visit_values(visitor);
+        }
+    }
+
+    /// Calls the visitor for every value of the statement, including values nested
+    /// in arrays and maps.
+    pub(crate) fn visit_values_mut(&mut self, visitor: &mut impl FnMut(&mut Value)) {
+        for condition in self.conditions.iter_mut() {
+            condition.visit_values_mut(visitor);
+        }
+        for condition in self.client_conditions.iter_mut() {
+            condition.visit_values_mut(visitor);
+        }
+        if let Some(having) = self.having.as_mut() {
+            having.visit_values_mut(visitor);
+        }
+        for cursor in self.start_at.iter_mut().chain(self.end_at.iter_mut()) {
+            for value in cursor.values.iter_mut() {
+                value.visit_values_mut(visitor);
+            }
+        }
+        for (_, select) in self.set_operations.iter_mut() {
+            select.visit_values_mut(visitor);
+        }
+    }
+
+    /// Calls the visitor for every compare operation of WHERE conditions.
+    pub(crate) fn visit_operations_mut(
+        &mut self,
+        visitor: &mut impl FnMut(&mut CompareOperations),
+    ) {
+        for condition in self
+            .conditions
+            .iter_mut()
+            .chain(self.client_conditions.iter_mut())
+        {
+            condition.visit_operations_mut(visitor);
+        }
+    }
+}
+
+/// Statement of a script.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FireSQLStatement {
+    select: FireSQLSelect,
+    span: std::ops::Range<usize>,
+}
+
+impl FireSQLStatement {
+    pub fn select(&self) -> &FireSQLSelect {
+        &self.select
+    }
+
+    pub fn into_select(self) -> FireSQLSelect {
+        self.select
+    }
+
+    /// Byte range of the statement in the script.
+    pub fn span(&self) -> std::ops::Range<usize> {
+        self.span.clone()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SelectProjection {
+    ObjectId(Option<String>),
+    Object,
+    Property(FieldPath, Option<String>),
+    /// Field of a referenced document, e.g. `author->name`.
+    Dereference(Vec<FieldPath>, Option<String>),
+    Aggregate(Aggregation, Option<String>),
+}
+
+impl SelectProjection {
+    /// Name of the result column - the alias if given.
+    pub(super) fn column_name(&self) -> String {
+        match self {
+            SelectProjection::ObjectId(alias) => alias.clone().unwrap_or_else(|| ":id".to_owned()),
+            SelectProjection::Object => "*".to_owned(),
+            SelectProjection::Property(path, alias) => {
+                alias.clone().unwrap_or_else(|| path.to_string())
+            }
+            SelectProjection::Dereference(paths, alias) => alias
+                .clone()
+                .unwrap_or_else(|| paths.iter().map(FieldPath::to_string).join("->")),
+            SelectProjection::Aggregate(aggregation, alias) => {
+                alias.clone().unwrap_or_else(|| aggregation.to_string())
+            }
+        }
+    }
+}
+
+/// Aggregation over all documents matching the query (or over groups of them).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Aggregation {
+    /// `COUNT(*)`
+    CountAll,
+    /// Number of documents with non null value of the field.
+    Count(FieldPath),
+    Sum(FieldPath),
+    Avg(FieldPath),
+    /// Calculated on the client side only.
+    Min(FieldPath),
+    /// Calculated on the client side only.
+    Max(FieldPath),
+}
+
+impl Aggregation {
+    /// Firestore aggregation queries support only counts, sums and averages.
+    pub(super) fn is_server_side(&self) -> bool {
+        !matches!(self, Aggregation::Min(_) | Aggregation::Max(_))
+    }
+
+    fn map_field(self, f: impl Fn(FieldPath) -> FieldPath) -> Self {
+        match self {
+            Aggregation::CountAll => Aggregation::CountAll,
+            Aggregation::Count(field) => Aggregation::Count(f(field)),
+            Aggregation::Sum(field) => Aggregation::Sum(f(field)),
+            Aggregation::Avg(field) => Aggregation::Avg(f(field)),
+            Aggregation::Min(field) => Aggregation::Min(f(field)),
+            Aggregation::Max(field) => Aggregation::Max(f(field)),
+        }
+    }
+
+    pub(super) fn field(&self) -> Option<&FieldPath> {
+        match self {
+            Aggregation::CountAll => None,
+            Aggregation::Count(field)
+            | Aggregation::Sum(field)
+            | Aggregation::Avg(field)
+            | Aggregation::Min(field)
+            | Aggregation::Max(field) => Some(field),
+        }
+    }
+}
+
+impl core::fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Aggregation::CountAll => write!(f, "COUNT(*)"),
+            Aggregation::Count(field) => write!(f, "COUNT({})", field),
+            Aggregation::Sum(field) => write!(f, "SUM({})", field),
+            Aggregation::Avg(field) => write!(f, "AVG({})", field),
+            Aggregation::Min(field) => write!(f, "MIN({})", field),
+            Aggregation::Max(field) => write!(f, "MAX({})", field),
+        }
+    }
+}
+
+/// Path to a (possibly nested) document field, one item per path segment.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct FieldPath(pub(super) Vec<String>);
+
+impl FieldPath {
+    pub(super) fn document_id() -> Self {
+        FieldPath(vec![DOCUMENT_ID_FIELD.to_owned()])
+    }
+
+    pub(super) fn is_document_id(&self) -> bool {
+        self.0.len() == 1 && self.0[0] == DOCUMENT_ID_FIELD
+    }
+}
+
+/// Formats the path the way Firestore expects it - segments that are not simple
+/// names are quoted with backticks.
+impl core::fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, segment) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ".")?;
+            }
+            let simple = segment
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if simple {
+                write!(f, "{}", segment)?;
+            } else {
+                let escaped = segment.replace('\\', "\\\\").replace('`', "\\`");
+                write!(f, "`{}`", escaped)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Collection {
+    pub(super) path: String,
+    /// Query includes all collections with the same name that are descendants
+    /// of the parent document (or of the database root).
+    pub(super) all_descendants: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Join {
+    pub(super) kind: JoinKind,
+    pub(super) collection: Collection,
+    pub(super) alias: String,
+    /// Field of the documents joined so far.
+    pub(super) left: FieldPath,
+    /// Field of the joined collection documents (or the document id).
+    pub(super) right: FieldPath,
+}
+
+/// Operator combining the rows of statements. Except for UNION ALL
+/// the combined rows are distinct.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetOperator {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+impl core::fmt::Display for SetOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetOperator::Union => write!(f, "UNION"),
+            SetOperator::UnionAll => write!(f, "UNION ALL"),
+            SetOperator::Intersect => write!(f, "INTERSECT"),
+            SetOperator::Except => write!(f, "EXCEPT"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum JoinKind {
+    Inner,
+    /// Documents without matching joined documents are kept.
+    Left,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Condition {
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+    IsNull(FieldPath),
+    Comparison(FieldPath, CompareOperations),
+    /// Condition on fields of the document referenced by the field.
+    Dereference(FieldPath, Box<Condition>),
+}
+
+impl Condition {
+    pub(crate) fn map_field_paths(self, f: &impl Fn(FieldPath) -> FieldPath) -> Condition {
+        match self {
+            Condition::And(conditions) => Condition::And(
+                conditions
+                    .into_iter()
+                    .map(|condition| condition.map_field_paths(f))
+                    .collect(),
+            ),
+            Condition::Or(conditions) => Condition::Or(
+                conditions
+                    .into_iter()
+                    .map(|condition| condition.map_field_paths(f))
+                    .collect(),
+            ),
+            Condition::Not(condition) => Condition::Not(Box::new(condition.map_field_paths(f))),
+            // fields of the referenced document are not affected
+            Condition::Dereference(reference, condition) => {
+                Condition::Dereference(f(reference), condition)
+            }
+            Condition::IsNull(field) => Condition::IsNull(f(field)),
+            Condition::Comparison(field, operation) => Condition::Comparison(f(field), operation),
+        }
+    }
+
+    /// Rewrites `= NULL` into `IS NULL` and `!= NULL` into `IS NOT NULL`, as Firestore
+    /// does not accept NULL in comparisons.
+    fn with_null_checks(self) -> Condition {
+        match self {
+            Condition::And(conditions) => Condition::And(
+                conditions
+                    .into_iter()
+                    .map(Condition::with_null_checks)
+                    .collect(),
+            ),
+            Condition::Or(conditions) => Condition::Or(
+                conditions
+                    .into_iter()
+                    .map(Condition::with_null_checks)
+                    .collect(),
+            ),
+            Condition::Not(condition) => Condition::Not(Box::new(condition.with_null_checks())),
+            Condition::Dereference(reference, condition) => {
+                Condition::Dereference(reference, Box::new(condition.with_null_checks()))
+            }
+            Condition::Comparison(field, CompareOperations::Equal(Value::Null)) => {
+                Condition::IsNull(field)
+            }
+            Condition::Comparison(field, CompareOperations::NotEqual(Value::Null)) => {
+                Condition::Not(Box::new(Condition::IsNull(field)))
+            }
+            Condition::Comparison(field, CompareOperations::InSubquery(mut select)) => {
+                select.rewrite_null_comparisons();
+                Condition::Comparison(field, CompareOperations::InSubquery(select))
+            }
+            condition => condition,
+        }
+    }
+
+    fn field_paths(&self) -> Vec<&FieldPath> {
+        match self {
+            Condition::And(conditions) | Condition::Or(conditions) => conditions
+                .iter()
+                .flat_map(|condition| condition.field_paths())
+                .collect(),
+            Condition::Not(condition) => condition.field_paths(),
+            Condition::IsNull(field)
+            | Condition::Comparison(field, _)
+            | Condition::Dereference(field, _) => vec![field],
+        }
+    }
+
+    fn has_dereference(&self) -> bool {
+        match self {
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().any(Condition::has_dereference)
+            }
+            Condition::Not(condition) => condition.has_dereference(),
+            Condition::IsNull(_) | Condition::Comparison(..) => false,
+            Condition::Dereference(..) => true,
+        }
+    }
+
+    fn visit_values<'a>(&'a self, visitor: &mut impl FnMut(&'a Value)) {
+        match self {
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions.iter() {
+                    condition.visit_values(visitor);
+                }
+            }
+            Condition::Not(condition) | Condition::Dereference(_, condition) => {
+                condition.visit_values(visitor)
+            }
+            Condition::IsNull(_) => {}
+            Condition::Comparison(_, compare_operations) => {
+                compare_operations.visit_values(visitor)
+            }
+        }
+    }
+
+    fn visit_values_mut(&mut self, visitor: &mut impl FnMut(&mut Value)) {
+        match self {
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions.iter_mut() {
+                    condition.visit_values_mut(visitor);
+                }
+            }
+            Condition::Not(condition) | Condition::Dereference(_, condition) => {
+                condition.visit_values_mut(visitor)
+            }
+            Condition::IsNull(_) => {}
+            Condition::Comparison(_, compare_operations) => {
+                compare_operations.visit_values_mut(visitor)
+            }
+        }
+    }
+
+    fn visit_operations_mut(&mut self, visitor: &mut impl FnMut(&mut CompareOperations)) {
+        match self {
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions.iter_mut() {
+                    condition.visit_operations_mut(visitor);
+                }
+            }
+            Condition::Not(condition) | Condition::Dereference(_, condition) => {
+                condition.visit_operations_mut(visitor)
+            }
+            Condition::IsNull(_) => {}
+            Condition::Comparison(_, compare_operations) => visitor(compare_operations),
+        }
+    }
+}
+
+/// Condition on groups, evaluated on the client side.
+#[derive(Debug, PartialEq, Clone)]
+pub enum HavingCondition {
+    And(Vec<HavingCondition>),
+    Or(Vec<HavingCondition>),
+    Not(Box<HavingCondition>),
+    Comparison(HavingOperand, CompareOperations),
+}
+
+impl HavingCondition {
+    fn visit_values<'a>(&'a self, visitor: &mut impl FnMut(&'a Value)) {
+        match self {
+            HavingCondition::And(conditions) | HavingCondition::Or(conditions) => {
+                for condition in conditions.iter() {
+                    condition.visit_values(visitor);
+                }
+            }
+            HavingCondition::Not(condition) => condition.visit_values(visitor),
+            HavingCondition::Comparison(_, compare_operations) => {
+                compare_operations.visit_values(visitor)
+            }
+        }
+    }
+
+    fn visit_values_mut(&mut self, visitor: &mut impl FnMut(&mut Value)) {
+        match self {
+            HavingCondition::And(conditions) | HavingCondition::Or(conditions) => {
+                for condition in conditions.iter_mut() {
+                    condition.visit_values_mut(visitor);
+                }
+            }
+            HavingCondition::Not(condition) => condition.visit_values_mut(visitor),
+            HavingCondition::Comparison(_, compare_operations) => {
+                compare_operations.visit_values_mut(visitor)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum HavingOperand {
+    /// One of the GROUP BY fields.
+    Field(FieldPath),
+    Aggregate(Aggregation),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CompareOperations {
+    Equal(Value),
     NotEqual(Value),
     GreaterThan(Value),
+    GreaterThanOrEqual(Value),
     LessThan(Value),
+    LessThanOrEqual(Value),
+    In(Vec<Value>),
+    NotIn(Vec<Value>),
+    ArrayContains(Value),
+    ArrayContainsAny(Vec<Value>),
+    /// `IN (SELECT ...)`, replaced with the list of selected values before execution.
+    InSubquery(Box<FireSQLSelect>),
+}
+
+impl CompareOperations {
+    fn visit_values<'a>(&'a self, visitor: &mut impl FnMut(&'a Value)) {
+        match self {
+            CompareOperations::Equal(value)
+            | CompareOperations::NotEqual(value)
+            | CompareOperations::GreaterThan(value)
+            | CompareOperations::GreaterThanOrEqual(value)
+            | CompareOperations::LessThan(value)
+            | CompareOperations::LessThanOrEqual(value)
+            | CompareOperations::ArrayContains(value) => value.visit_values(visitor),
+            CompareOperations::In(values)
+            | CompareOperations::NotIn(values)
+            | CompareOperations::ArrayContainsAny(values) => {
+                for value in values.iter() {
+                    value.visit_values(visitor);
+                }
+            }
+            CompareOperations::InSubquery(select) => select.visit_values(visitor),
+        }
+    }
+
+    fn visit_values_mut(&mut self, visitor: &mut impl FnMut(&mut Value)) {
+        match self {
+            CompareOperations::Equal(value)
+            | CompareOperations::NotEqual(value)
+            | CompareOperations::GreaterThan(value)
+            | CompareOperations::GreaterThanOrEqual(value)
+            | CompareOperations::LessThan(value)
+            | CompareOperations::LessThanOrEqual(value)
+            | CompareOperations::ArrayContains(value) => value.visit_values_mut(visitor),
+            CompareOperations::In(values)
+            | CompareOperations::NotIn(values)
+            | CompareOperations::ArrayContainsAny(values) => {
+                for value in values.iter_mut() {
+                    value.visit_values_mut(visitor);
+                }
+            }
+            CompareOperations::InSubquery(select) => select.visit_values_mut(visitor),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
-    Number(f64),
+    Integer(i64),
+    Double(f64),
     String(String),
     Bool(bool),
     Reference(String),
+    /// Seconds and nanoseconds since the Unix epoch.
+    Timestamp(i64, i32),
+    /// Latitude and longitude in degrees.
+    GeoPoint(f64, f64),
+    Bytes(Vec<u8>),
+    Null,
+    Array(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Parameter(Parameter),
+}
+
+impl Value {
+    fn visit_values<'a>(&'a self, visitor: &mut impl FnMut(&'a Value)) {
+        visitor(self);
+        match self {
+            Value::Array(values) => {
+                for value in values.iter() {
+                    value.visit_values(visitor);
+                }
+            }
+            Value::Map(entries) => {
+                for value in entries.values() {
+                    value.visit_values(visitor);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_values_mut(&mut self, visitor: &mut impl FnMut(&mut Value)) {
+        visitor(self);
+        match self {
+            Value::Array(values) => {
+                for value in values.iter_mut() {
+                    value.visit_values_mut(visitor);
+                }
+            }
+            Value::Map(entries) => {
+                for value in entries.values_mut() {
+                    value.visit_values_mut(visitor);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Double(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_owned())
+    }
+}
+
+/// Placeholder for a value bound after parsing.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Parameter {
+    /// `$1`, `$2`, ...
+    Positional(usize),
+    /// `:name`
+    Named(String),
+}
+
+impl From<usize> for Parameter {
+    fn from(position: usize) -> Self {
+        Parameter::Positional(position)
+    }
+}
+
+impl From<&str> for Parameter {
+    fn from(name: &str) -> Self {
+        Parameter::Named(name.to_owned())
+    }
+}
+
+impl core::fmt::Display for Parameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Parameter::Positional(position) => write!(f, "${}", position),
+            Parameter::Named(name) => write!(f, ":{}", name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OrderBy {
+    pub(super) field: FieldPath,
+    pub(super) direction: OrderDirection,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OrderDirection {
+    Ascending,
+    Descending,
+}
+
+/// Query cursor. `inclusive` cursor includes documents matching its values
+/// (`START AT` and `END AT`), the other excludes them (`START AFTER` and `END BEFORE`).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cursor {
+    pub(super) values: Vec<Value>,
+    pub(super) inclusive: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Limit {
+    First(u32),
+    Last(u32),
 }
 
 impl TryFrom<&str> for FireSQLSelect {
@@ -220,6 +2140,11 @@ impl TryFrom<&str> for FireSQLSelect {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn field(path: &str) -> FieldPath {
+        FieldPath(path.split('.').map(str::to_owned).collect())
+    }
+
     #[test]
     fn parse_simple_statement() {
         let result = FireSQLParser::parse(
@@ -230,14 +2155,27 @@ mod tests {
         assert_eq!(
             result,
             Ok(FireSQLSelect {
+                distinct: false,
                 projections: vec![
                     SelectProjection::Object,
-                    SelectProjection::Property("company".to_owned()),
+                    SelectProjection::Property(field("company"), None),
                 ],
                 collection: Collection {
                     path: "users/USER_ID/achievements".to_owned(),
+                    all_descendants: false,
                 },
+                collection_alias: None,
+                joins: vec![],
                 conditions: vec![],
+                client_conditions: vec![],
+                group_by: vec![],
+                having: None,
+                order_by: vec![],
+                start_at: None,
+                end_at: None,
+                limit: None,
+                offset: None,
+                set_operations: vec![],
             }),
         )
     }
@@ -272,21 +2210,1191 @@ mod tests {
         assert_eq!(
             result.unwrap(),
             FireSQLSelect {
-                projections: vec![SelectProjection::Property("a".to_owned())],
+                distinct: false,
+                projections: vec![SelectProjection::Property(field("a"), None)],
                 collection: Collection {
-                    path: "b".to_owned()
+                    path: "b".to_owned(),
+                    all_descendants: false,
                 },
+                collection_alias: None,
+                joins: vec![],
                 conditions: vec![
                     Condition::Comparison(
-                        "c".to_owned(),
+                        field("c"),
                         CompareOperations::Equal(Value::String("d".to_owned()))
                     ),
-                    Condition::Comparison(
-                        "e".to_owned(),
-                        CompareOperations::Equal(Value::Number(5.0))
-                    )
-                ]
+                    Condition::Comparison(field("e"), CompareOperations::Equal(Value::Integer(5)))
+                ],
+                client_conditions: vec![],
+                group_by: vec![],
+                having: None,
+                order_by: vec![],
+                start_at: None,
+                end_at: None,
+                limit: None,
+                offset: None,
+                set_operations: vec![],
             }
         )
     }
+
+    #[test]
+    fn or_and_parentheses() {
+        let result = FireSQLParser::parse(
+            r#"
+                select a from b
+                where a = 1 and (b = 2 or c > 3) or d = "x"
+            "#,
+        );
+        assert_eq!(
+            result.unwrap().conditions,
+            vec![Condition::Or(vec![
+                Condition::And(vec![
+                    Condition::Comparison(field("a"), CompareOperations::Equal(Value::Integer(1))),
+                    Condition::Or(vec![
+                        Condition::Comparison(
+                            field("b"),
+                            CompareOperations::Equal(Value::Integer(2))
+                        ),
+                        Condition::Comparison(
+                            field("c"),
+                            CompareOperations::GreaterThan(Value::Integer(3))
+                        ),
+                    ]),
+                ]),
+                Condition::Comparison(
+                    field("d"),
+                    CompareOperations::Equal(Value::String("x".to_owned()))
+                ),
+            ])]
+        )
+    }
+
+    #[test]
+    fn nested_and_groups_are_flattened() {
+        let result = FireSQLParser::parse("select a from b where (a = 1 and b = 2) and ((c = 3))");
+        assert_eq!(result.unwrap().conditions.len(), 3);
+    }
+
+    #[test]
+    fn negated_conditions_are_rewritten() {
+        let result = FireSQLParser::parse(
+            r#"
+                select a from b
+                where not a = 1 and not (b is null or c != "x") and d is not null
+            "#,
+        );
+        assert_eq!(
+            result.unwrap().conditions,
+            vec![
+                Condition::Comparison(field("a"), CompareOperations::NotEqual(Value::Integer(1))),
+                Condition::Not(Box::new(Condition::IsNull(field("b")))),
+                Condition::Comparison(
+                    field("c"),
+                    CompareOperations::Equal(Value::String("x".to_owned()))
+                ),
+                Condition::Not(Box::new(Condition::IsNull(field("d")))),
+            ]
+        )
+    }
+
+    #[test]
+    fn inclusive_comparisons() {
+        let result = FireSQLParser::parse("select a from b where a >= 1 and b <= 2");
+        assert_eq!(
+            result.unwrap().conditions,
+            vec![
+                Condition::Comparison(
+                    field("a"),
+                    CompareOperations::GreaterThanOrEqual(Value::Integer(1))
+                ),
+                Condition::Comparison(
+                    field("b"),
+                    CompareOperations::LessThanOrEqual(Value::Integer(2))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn in_and_not_in_lists() {
+        let result =
+            FireSQLParser::parse(r#"select a from b where a in (1, "x") and not b in (true)"#);
+        assert_eq!(
+            result.unwrap().conditions,
+            vec![
+                Condition::Comparison(
+                    field("a"),
+                    CompareOperations::In(vec![Value::Integer(1), Value::String("x".to_owned())])
+                ),
+                Condition::Comparison(
+                    field("b"),
+                    CompareOperations::NotIn(vec![Value::Bool(true)])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn too_long_not_in_list() {
+        let values = (0..11)
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let result = FireSQLParser::parse(&format!("select a from b where a not in ({values})"));
+        assert!(matches!(result, Err(ParseError::TooManyValues(_))));
+    }
+
+    #[test]
+    fn array_contains_conditions() {
+        let result = FireSQLParser::parse(
+            r#"select a from b
+            where tags contains "admin"
+            and array_contains(roles, "owner")
+            and ARRAY_CONTAINS_ANY(tags, ("a", "b"))"#,
+        );
+        assert_eq!(
+            result.unwrap().conditions,
+            vec![
+                Condition::Comparison(
+                    field("tags"),
+                    CompareOperations::ArrayContains(Value::String("admin".to_owned()))
+                ),
+                Condition::Comparison(
+                    field("roles"),
+                    CompareOperations::ArrayContains(Value::String("owner".to_owned()))
+                ),
+                Condition::Comparison(
+                    field("tags"),
+                    CompareOperations::ArrayContainsAny(vec![
+                        Value::String("a".to_owned()),
+                        Value::String("b".to_owned())
+                    ])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_negation() {
+        let result = FireSQLParser::parse("select a from b where not a > 1");
+        assert_eq!(
+            result,
+            Err(ParseError::UnsupportedNegation("not a > 1".to_owned()))
+        );
+        for statement in [
+            "select a from b where not a >= 1",
+            "select a from b where not (a < 1 or c = 2)",
+            "select a from b where not a <= 1",
+        ] {
+            assert!(
+                matches!(
+                    FireSQLParser::parse(statement),
+                    Err(ParseError::UnsupportedNegation(_))
+                ),
+                "{statement}"
+            );
+        }
+
+        let result = FireSQLParser::parse(r#"select a from b where not tags contains "x""#);
+        assert_eq!(
+            result,
+            Err(ParseError::UnsupportedNegation(
+                r#"not tags contains "x""#.to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn order_by_fields() {
+        let result =
+            FireSQLParser::parse("select a from b where a > 1 order by a desc, b, :id ASC");
+        assert_eq!(
+            result.unwrap().order_by,
+            vec![
+                OrderBy {
+                    field: field("a"),
+                    direction: OrderDirection::Descending
+                },
+                OrderBy {
+                    field: field("b"),
+                    direction: OrderDirection::Ascending
+                },
+                OrderBy {
+                    field: FieldPath::document_id(),
+                    direction: OrderDirection::Ascending
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_and_offset() {
+        let result = FireSQLParser::parse("select a from b order by a limit 10 offset 20").unwrap();
+        assert_eq!(result.limit, Some(Limit::First(10)));
+        assert_eq!(result.offset, Some(20));
+
+        let result = FireSQLParser::parse("select a from b order by a limit to last 5").unwrap();
+        assert_eq!(result.limit, Some(Limit::Last(5)));
+        assert_eq!(result.offset, None);
+    }
+
+    #[test]
+    fn limit_to_last_requires_order_by() {
+        let result = FireSQLParser::parse("select a from b limit to last 5");
+        assert!(matches!(result, Err(ParseError::InvalidLimit(_))));
+    }
+
+    #[test]
+    fn cursors() {
+        let result = FireSQLParser::parse(
+            r#"select a from b order by created desc start after ("2024-01-01", "abc") end at ("2025-01-01")"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.start_at,
+            Some(Cursor {
+                values: vec![
+                    Value::String("2024-01-01".to_owned()),
+                    Value::String("abc".to_owned())
+                ],
+                inclusive: false
+            })
+        );
+        assert_eq!(
+            result.end_at,
+            Some(Cursor {
+                values: vec![Value::String("2025-01-01".to_owned())],
+                inclusive: true
+            })
+        );
+        assert_eq!(
+            result.order_by[1],
+            OrderBy {
+                field: FieldPath::document_id(),
+                direction: OrderDirection::Descending
+            }
+        );
+    }
+
+    #[test]
+    fn cursor_with_too_many_values() {
+        for statement in [
+            "select a from b order by a, :id start at (1, 2, 3)",
+            "select a from b order by a start at (1, 2)",
+            "select a from b order by :id end before (1)",
+            "select count(*) from users start at (1)",
+            "select count(*) from users order by a start at (1, 'x')",
+        ] {
+            assert!(
+                matches!(
+                    FireSQLParser::parse(statement),
+                    Err(ParseError::InvalidCursor(_))
+                ),
+                "{statement}"
+            );
+        }
+        // the implicit document id order does not satisfy LIMIT TO LAST
+        let result = FireSQLParser::parse("select a from b start at ('x') limit to last 5");
+        assert!(matches!(result, Err(ParseError::InvalidLimit(_))));
+    }
+
+    #[test]
+    fn collection_groups() {
+        let result = FireSQLParser::parse("select a from group achievements").unwrap();
+        assert_eq!(
+            result.collection,
+            Collection {
+                path: "achievements".to_owned(),
+                all_descendants: true,
+            }
+        );
+
+        let result = FireSQLParser::parse("select a from users/u1/**/achievements").unwrap();
+        assert_eq!(
+            result.collection,
+            Collection {
+                path: "users/u1/achievements".to_owned(),
+                all_descendants: true,
+            }
+        );
+
+        let result = FireSQLParser::parse("select a from users/**/achievements");
+        assert!(matches!(result, Err(ParseError::InvalidCollectionPath(_))));
+
+        for collection in ["groups", "group1", "group_members"] {
+            let result = FireSQLParser::parse(&format!("select a from {collection}")).unwrap();
+            assert_eq!(
+                result.collection,
+                Collection {
+                    path: collection.to_owned(),
+                    all_descendants: false,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn nested_and_quoted_field_paths() {
+        let result = FireSQLParser::parse(
+            r#"select address.city, `my-field`.`sub key`, _private
+            from b
+            where `order` = 1 and `a\`b`.c is null
+            order by address.city"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::Property(field("address.city"), None),
+                SelectProjection::Property(
+                    FieldPath(vec!["my-field".to_owned(), "sub key".to_owned()]),
+                    None
+                ),
+                SelectProjection::Property(field("_private"), None),
+            ]
+        );
+        assert_eq!(
+            result.conditions,
+            vec![
+                Condition::Comparison(field("order"), CompareOperations::Equal(Value::Integer(1))),
+                Condition::IsNull(FieldPath(vec!["a`b".to_owned(), "c".to_owned()])),
+            ]
+        );
+        assert_eq!(result.order_by[0].field, field("address.city"));
+    }
+
+    #[test]
+    fn field_path_display() {
+        assert_eq!(field("address.city").to_string(), "address.city");
+        assert_eq!(
+            FieldPath(vec![
+                "my-field".to_owned(),
+                "1st".to_owned(),
+                "a`b".to_owned()
+            ])
+            .to_string(),
+            r"`my-field`.`1st`.`a\`b`"
+        );
+    }
+
+    #[test]
+    fn integer_and_double_literals() {
+        let result = FireSQLParser::parse(
+            "select a from b where a in (9007199254740993, -1, 1.5, 2e3, 1.0E-2)",
+        )
+        .unwrap();
+        assert_eq!(
+            result.conditions,
+            vec![Condition::Comparison(
+                field("a"),
+                CompareOperations::In(vec![
+                    Value::Integer(9007199254740993),
+                    Value::Integer(-1),
+                    Value::Double(1.5),
+                    Value::Double(2000.0),
+                    Value::Double(0.01),
+                ])
+            )]
+        );
+    }
+
+    #[test]
+    fn integer_out_of_range() {
+        let result = FireSQLParser::parse("select a from b where a = 9223372036854775808");
+        assert_eq!(
+            result,
+            Err(ParseError::InvalidNumber("9223372036854775808".to_owned()))
+        );
+    }
+
+    #[test]
+    fn references() {
+        let result = FireSQLParser::parse(
+            r#"select a from b
+            where author = ref(users/abc)
+            or author = ref("projects/p-1/databases/(default)/documents/users/a-b")"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.conditions,
+            vec![Condition::Or(vec![
+                Condition::Comparison(
+                    field("author"),
+                    CompareOperations::Equal(Value::Reference("users/abc".to_owned()))
+                ),
+                Condition::Comparison(
+                    field("author"),
+                    CompareOperations::Equal(Value::Reference(
+                        "projects/p-1/databases/(default)/documents/users/a-b".to_owned()
+                    ))
+                ),
+            ])]
+        );
+
+        let result = FireSQLParser::parse("select a from b where author = ref(users)");
+        assert_eq!(
+            result,
+            Err(ParseError::InvalidReference("users".to_owned()))
+        );
+    }
+
+    #[test]
+    fn typed_literals() {
+        let result = FireSQLParser::parse(
+            r#"select a from b
+            where created > TIMESTAMP '2024-05-01T00:00:00Z'
+            and location = GEOPOINT(52.2, 21)
+            and hash = BYTES x'deadBEEF'
+            and deleted = null"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.conditions,
+            vec![
+                Condition::Comparison(
+                    field("created"),
+                    CompareOperations::GreaterThan(Value::Timestamp(1714521600, 0))
+                ),
+                Condition::Comparison(
+                    field("location"),
+                    CompareOperations::Equal(Value::GeoPoint(52.2, 21.0))
+                ),
+                Condition::Comparison(
+                    field("hash"),
+                    CompareOperations::Equal(Value::Bytes(vec![0xde, 0xad, 0xbe, 0xef]))
+                ),
+                Condition::IsNull(field("deleted")),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_typed_literals() {
+        let result = FireSQLParser::parse("select a from b where created > TIMESTAMP '2024-05'");
+        assert_eq!(
+            result,
+            Err(ParseError::InvalidTimestamp("2024-05".to_owned()))
+        );
+        let result = FireSQLParser::parse("select a from b where location = GEOPOINT(91, 0)");
+        assert_eq!(
+            result,
+            Err(ParseError::InvalidGeoPoint("GEOPOINT(91, 0)".to_owned()))
+        );
+    }
+
+    #[test]
+    fn array_and_map_literals() {
+        let result = FireSQLParser::parse(
+            r#"select a from b
+            where coords = [1, 2]
+            and meta = {"v": 1, "k": ["x", {}]}
+            and pairs in ([], [true])"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.conditions,
+            vec![
+                Condition::Comparison(
+                    field("coords"),
+                    CompareOperations::Equal(Value::Array(vec![
+                        Value::Integer(1),
+                        Value::Integer(2)
+                    ]))
+                ),
+                Condition::Comparison(
+                    field("meta"),
+                    CompareOperations::Equal(Value::Map(BTreeMap::from([
+                        ("v".to_owned(), Value::Integer(1)),
+                        (
+                            "k".to_owned(),
+                            Value::Array(vec![
+                                Value::String("x".to_owned()),
+                                Value::Map(BTreeMap::new())
+                            ])
+                        ),
+                    ])))
+                ),
+                Condition::Comparison(
+                    field("pairs"),
+                    CompareOperations::In(vec![
+                        Value::Array(vec![]),
+                        Value::Array(vec![Value::Bool(true)])
+                    ])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        let result = FireSQLParser::parse(
+            r#"select a from b
+            where a in ("say \"hi\"", "line\nbreak\t\\\/", "caf\u00e9 \ud83d\ude00", 'it''s "ok"')"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.conditions,
+            vec![Condition::Comparison(
+                field("a"),
+                CompareOperations::In(vec![
+                    Value::String(r#"say "hi""#.to_owned()),
+                    Value::String("line\nbreak\t\\/".to_owned()),
+                    Value::String("café 😀".to_owned()),
+                    Value::String(r#"it's "ok""#.to_owned()),
+                ])
+            )]
+        );
+    }
+
+    #[test]
+    fn bind_parameters() {
+        let select = FireSQLParser::parse(
+            "select a from b where a = $1 and b in ($2, :name) and c = [:name] order by a start at ($1)",
+        )
+        .unwrap();
+        assert_eq!(
+            select.unbound_parameters(),
+            vec![
+                Parameter::Positional(1),
+                Parameter::Positional(2),
+                Parameter::Named("name".to_owned())
+            ]
+        );
+
+        let select = select.bind(1, 10).unwrap().bind("name", "x").unwrap();
+        assert_eq!(select.unbound_parameters(), vec![Parameter::Positional(2)]);
+        assert_eq!(
+            select.conditions,
+            vec![
+                Condition::Comparison(field("a"), CompareOperations::Equal(Value::Integer(10))),
+                Condition::Comparison(
+                    field("b"),
+                    CompareOperations::In(vec![
+                        Value::Parameter(Parameter::Positional(2)),
+                        Value::String("x".to_owned())
+                    ])
+                ),
+                Condition::Comparison(
+                    field("c"),
+                    CompareOperations::Equal(Value::Array(vec![Value::String("x".to_owned())]))
+                ),
+            ]
+        );
+        assert_eq!(select.start_at.unwrap().values, vec![Value::Integer(10)]);
+    }
+
+    #[test]
+    fn invalid_bindings() {
+        let select = FireSQLParser::parse(
+            "select a from b where a = $1 and c in ($2) order by :id start at ($3)",
+        )
+        .unwrap();
+        assert_eq!(
+            select.clone().bind(4, 1),
+            Err(ParseError::UnknownParameter("$4".to_owned()))
+        );
+        assert_eq!(
+            select.clone().bind("name", 1),
+            Err(ParseError::UnknownParameter(":name".to_owned()))
+        );
+        // a parameter cannot be bound twice
+        assert_eq!(
+            select.clone().bind(1, 1).unwrap().bind(1, 2),
+            Err(ParseError::UnknownParameter("$1".to_owned()))
+        );
+        assert_eq!(
+            select.clone().bind(2, Value::Reference("users".to_owned())),
+            Err(ParseError::InvalidReference("users".to_owned()))
+        );
+        assert!(matches!(
+            select.clone().bind(3, 10),
+            Err(ParseError::InvalidCursor(_))
+        ));
+        assert!(select.bind(3, "u1").is_ok());
+    }
+
+    #[test]
+    fn null_comparisons() {
+        let not_null = |name: &str| Condition::Not(Box::new(Condition::IsNull(field(name))));
+        let select =
+            FireSQLParser::parse("select a from b where a != null and not (b = null or c != null)")
+                .unwrap();
+        assert_eq!(
+            select.conditions,
+            vec![not_null("a"), not_null("b"), Condition::IsNull(field("c"))]
+        );
+
+        let select = FireSQLParser::parse("select a from b where a = $1 and b != :x")
+            .unwrap()
+            .bind(1, Value::Null)
+            .unwrap()
+            .bind("x", Value::Null)
+            .unwrap();
+        assert_eq!(
+            select.conditions,
+            vec![Condition::IsNull(field("a")), not_null("b")]
+        );
+    }
+
+    #[test]
+    fn projection_aliases() {
+        let result =
+            FireSQLParser::parse("select :id as doc_id, a.b AS `b value`, a.b, * from c").unwrap();
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::ObjectId(Some("doc_id".to_owned())),
+                SelectProjection::Property(field("a.b"), Some("b value".to_owned())),
+                SelectProjection::Property(field("a.b"), None),
+                SelectProjection::Object,
+            ]
+        );
+        assert_eq!(
+            result
+                .projections
+                .iter()
+                .map(SelectProjection::column_name)
+                .collect::<Vec<_>>(),
+            vec!["doc_id", "b value", "a.b", "*"]
+        );
+        assert!(FireSQLParser::parse("select * as all from c").is_err());
+        // AS is a keyword only when followed by a word boundary
+        assert!(matches!(
+            FireSQLParser::parse("select a asdf from b"),
+            Err(ParseError::GrammarError(_))
+        ));
+        let result = FireSQLParser::parse("select a as asdf, b AS\n`as` from c").unwrap();
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::Property(field("a"), Some("asdf".to_owned())),
+                SelectProjection::Property(field("b"), Some("as".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_script() {
+        let script = r#"
+            -- all users
+            select * from users;
+
+            /* ordered
+               achievements */
+            select :id from users/u1/achievements order by :id -- by id
+            ;;
+            select a from b where c = "--not a comment"
+        "#;
+        let statements = FireSQLParser::parse_script(script).unwrap();
+        assert_eq!(statements.len(), 3);
+        assert_eq!(&script[statements[0].span()], "select * from users");
+        assert_eq!(
+            &script[statements[1].span()],
+            "select :id from users/u1/achievements order by :id -- by id"
+        );
+        assert_eq!(
+            statements[2].select().conditions,
+            vec![Condition::Comparison(
+                field("c"),
+                CompareOperations::Equal(Value::String("--not a comment".to_owned()))
+            )]
+        );
+        assert_eq!(
+            statements[1].select(),
+            &FireSQLParser::parse("select :id from users/u1/achievements order by :id;").unwrap()
+        );
+
+        assert_eq!(
+            FireSQLParser::parse_script(" -- nothing here\n").unwrap(),
+            vec![]
+        );
+        assert!(FireSQLParser::parse_script("select * from a select * from b").is_err());
+    }
+
+    #[test]
+    fn aggregations() {
+        let result = FireSQLParser::parse(
+            "select count(*), COUNT(a) as with_a, sum(b.c), Avg(`d-e`) from f where g > 1",
+        )
+        .unwrap();
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::Aggregate(Aggregation::CountAll, None),
+                SelectProjection::Aggregate(
+                    Aggregation::Count(field("a")),
+                    Some("with_a".to_owned())
+                ),
+                SelectProjection::Aggregate(Aggregation::Sum(field("b.c")), None),
+                SelectProjection::Aggregate(
+                    Aggregation::Avg(FieldPath(vec!["d-e".to_owned()])),
+                    None
+                ),
+            ]
+        );
+        assert_eq!(
+            result
+                .projections
+                .iter()
+                .map(SelectProjection::column_name)
+                .collect::<Vec<_>>(),
+            vec!["COUNT(*)", "with_a", "SUM(b.c)", "AVG(`d-e`)"]
+        );
+
+        // `count` is still a valid field name
+        let result = FireSQLParser::parse("select count from f").unwrap();
+        assert_eq!(
+            result.projections,
+            vec![SelectProjection::Property(field("count"), None)]
+        );
+
+        assert!(matches!(
+            FireSQLParser::parse("select a, count(*) from f"),
+            Err(ParseError::InvalidProjection(_))
+        ));
+        assert!(FireSQLParser::parse("select sum(*) from f").is_err());
+
+        assert!(
+            FireSQLParser::parse("select count(*), sum(a), sum(b), avg(c), count(d) from f")
+                .is_ok()
+        );
+        assert!(matches!(
+            FireSQLParser::parse(
+                "select count(*), sum(a), sum(b), avg(c), count(d), avg(e) from f"
+            ),
+            Err(ParseError::InvalidProjection(_))
+        ));
+        assert!(matches!(
+            FireSQLParser::parse("select count(*) from f order by a start at (1)"),
+            Err(ParseError::InvalidCursor(_))
+        ));
+        assert!(FireSQLParser::parse("select count(*) from f limit 1 offset 1").is_ok());
+    }
+
+    #[test]
+    fn group_by_and_having() {
+        let result = FireSQLParser::parse(
+            r#"select status, count(*) as orders, max(total)
+            from orders
+            where total > 0
+            group by status
+            having orders > 5 and not (min(total) < 10 or status = "new")"#,
+        )
+        .unwrap();
+        assert_eq!(result.group_by, vec![field("status")]);
+        assert_eq!(
+            result.having,
+            Some(HavingCondition::And(vec![
+                HavingCondition::Comparison(
+                    HavingOperand::Aggregate(Aggregation::CountAll),
+                    CompareOperations::GreaterThan(Value::Integer(5))
+                ),
+                HavingCondition::Not(Box::new(HavingCondition::Or(vec![
+                    HavingCondition::Comparison(
+                        HavingOperand::Aggregate(Aggregation::Min(field("total"))),
+                        CompareOperations::LessThan(Value::Integer(10))
+                    ),
+                    HavingCondition::Comparison(
+                        HavingOperand::Field(field("status")),
+                        CompareOperations::Equal(Value::String("new".to_owned()))
+                    ),
+                ]))),
+            ]))
+        );
+        assert_eq!(
+            result
+                .projections
+                .iter()
+                .map(SelectProjection::column_name)
+                .collect::<Vec<_>>(),
+            vec!["status", "orders", "MAX(total)"]
+        );
+
+        // collection group and GROUP BY in one statement
+        assert!(FireSQLParser::parse(
+            "select a, count(*) from group items group by a having count(*) > 1"
+        )
+        .is_ok());
+        // without GROUP BY all documents are a single group
+        assert!(FireSQLParser::parse("select min(a) from b having max(a) > 1").is_ok());
+    }
+
+    #[test]
+    fn invalid_grouping() {
+        assert!(matches!(
+            FireSQLParser::parse("select a, b, count(*) from c group by a"),
+            Err(ParseError::InvalidGrouping(_))
+        ));
+        assert!(matches!(
+            FireSQLParser::parse("select *, count(*) from c group by a"),
+            Err(ParseError::InvalidGrouping(_))
+        ));
+        assert!(matches!(
+            FireSQLParser::parse("select a from c group by a having b > 1"),
+            Err(ParseError::InvalidGrouping(_))
+        ));
+        assert!(matches!(
+            FireSQLParser::parse("select a from c having a > 1"),
+            Err(ParseError::InvalidGrouping(_))
+        ));
+    }
+
+    #[test]
+    fn distinct() {
+        let result = FireSQLParser::parse("select distinct country, city from users").unwrap();
+        assert!(result.distinct);
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::Property(field("country"), None),
+                SelectProjection::Property(field("city"), None),
+            ]
+        );
+        assert!(
+            !FireSQLParser::parse("select country from users")
+                .unwrap()
+                .distinct
+        );
+        // `distinct` is still a valid field name
+        assert!(
+            !FireSQLParser::parse("select distinct, a from users")
+                .unwrap()
+                .distinct
+        );
+        assert_eq!(
+            FireSQLParser::parse("select distinctive from users")
+                .unwrap()
+                .projections,
+            vec![SelectProjection::Property(field("distinctive"), None)]
+        );
+
+        assert!(matches!(
+            FireSQLParser::parse("select distinct * from users"),
+            Err(ParseError::InvalidProjection(_))
+        ));
+        assert!(matches!(
+            FireSQLParser::parse("select distinct count(*) from users"),
+            Err(ParseError::InvalidProjection(_))
+        ));
+    }
+
+    #[test]
+    fn joins() {
+        let result = FireSQLParser::parse(
+            r#"select o.total, u.email, u.:id, :id from orders o
+            join users u on o.userId = u.:id
+            left outer join shops as s on s.code = o.shop
+            where o.status = "paid" and u.active = true
+            order by total desc"#,
+        )
+        .unwrap();
+        assert_eq!(result.collection_alias, Some("o".to_owned()));
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::Property(field("o.total"), None),
+                SelectProjection::Property(field("u.email"), None),
+                SelectProjection::Property(field("u.__name__"), None),
+                SelectProjection::ObjectId(None),
+            ]
+        );
+        assert_eq!(
+            result.joins,
+            vec![
+                Join {
+                    kind: JoinKind::Inner,
+                    collection: Collection {
+                        path: "users".to_owned(),
+                        all_descendants: false,
+                    },
+                    alias: "u".to_owned(),
+                    left: field("o.userId"),
+                    right: FieldPath::document_id(),
+                },
+                Join {
+                    kind: JoinKind::Left,
+                    collection: Collection {
+                        path: "shops".to_owned(),
+                        all_descendants: false,
+                    },
+                    alias: "s".to_owned(),
+                    left: field("o.shop"),
+                    right: field("code"),
+                },
+            ]
+        );
+        // conditions on the main collection are run by Firestore
+        assert_eq!(
+            result.conditions,
+            vec![Condition::Comparison(
+                field("status"),
+                CompareOperations::Equal(Value::String("paid".to_owned()))
+            )]
+        );
+        assert_eq!(
+            result.client_conditions,
+            vec![Condition::Comparison(
+                field("u.active"),
+                CompareOperations::Equal(Value::Bool(true))
+            )]
+        );
+        assert_eq!(result.order_by[0].field, field("total"));
+
+        // collections are referred to by their names without aliases
+        let result =
+            FireSQLParser::parse("select users.email from orders join users on userId = users.:id")
+                .unwrap();
+        assert_eq!(result.collection_alias, Some("orders".to_owned()));
+        assert_eq!(result.joins[0].left, field("orders.userId"));
+
+        // aliases may start with AS
+        let result = FireSQLParser::parse(
+            "select asdf.total from orders asdf join users u on asdf.userId = u.:id",
+        )
+        .unwrap();
+        assert_eq!(result.collection_alias, Some("asdf".to_owned()));
+        assert_eq!(
+            result.projections[0],
+            SelectProjection::Property(field("asdf.total"), None)
+        );
+        assert_eq!(result.joins[0].left, field("asdf.userId"));
+    }
+
+    #[test]
+    fn alias_without_joins() {
+        let result =
+            FireSQLParser::parse("select o.total, o.:id from orders o where o.total > 1").unwrap();
+        assert_eq!(result.collection_alias, None);
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::Property(field("total"), None),
+                SelectProjection::ObjectId(None),
+            ]
+        );
+        assert_eq!(
+            result.conditions,
+            vec![Condition::Comparison(
+                field("total"),
+                CompareOperations::GreaterThan(Value::Integer(1))
+            )]
+        );
+    }
+
+    #[test]
+    fn invalid_joins() {
+        for statement in [
+            "select a from orders o join users o on o.userId = o.:id",
+            "select a from orders o join users u on o.userId = o.id",
+            "select a from orders o join users u on u.shop = s.code join shops s on s.a = o.b",
+            "select a from orders o join users u on o.userId = u.:id order by u.name",
+            "select a from orders o join group items i on o.item = i.:id",
+            "select x.:id from orders o join users u on o.userId = u.:id",
+            "select x.:id from orders",
+        ] {
+            assert!(
+                matches!(
+                    FireSQLParser::parse(statement),
+                    Err(ParseError::InvalidJoin(_))
+                ),
+                "{statement}"
+            );
+        }
+    }
+
+    #[test]
+    fn dereferences() {
+        let result = FireSQLParser::parse(
+            r#"select author->name, author -> manager -> `e-mail` as email from posts
+            where published = true and author->address.city = "Paris"
+            and not author->manager->name in ("Bob")"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.projections,
+            vec![
+                SelectProjection::Dereference(vec![field("author"), field("name")], None),
+                SelectProjection::Dereference(
+                    vec![field("author"), field("manager"), field("e-mail")],
+                    Some("email".to_owned())
+                ),
+            ]
+        );
+        assert_eq!(result.projections[0].column_name(), "author->name");
+        // conditions on referenced documents are checked on the client side
+        assert_eq!(
+            result.conditions,
+            vec![Condition::Comparison(
+                field("published"),
+                CompareOperations::Equal(Value::Bool(true))
+            )]
+        );
+        assert_eq!(
+            result.client_conditions,
+            vec![
+                Condition::Dereference(
+                    field("author"),
+                    Box::new(Condition::Comparison(
+                        field("address.city"),
+                        CompareOperations::Equal(Value::String("Paris".to_owned()))
+                    ))
+                ),
+                Condition::Dereference(
+                    field("author"),
+                    Box::new(Condition::Dereference(
+                        field("manager"),
+                        Box::new(Condition::Comparison(
+                            field("name"),
+                            CompareOperations::NotIn(vec![Value::String("Bob".to_owned())])
+                        ))
+                    ))
+                ),
+            ]
+        );
+
+        assert!(matches!(
+            FireSQLParser::parse("select author->name from posts group by author"),
+            Err(ParseError::InvalidGrouping(_))
+        ));
+    }
+
+    #[test]
+    fn in_subqueries() {
+        let result = FireSQLParser::parse(
+            "select title from achievements where userId in (select :id from users where created > $1) and points > 10",
+        )
+        .unwrap()
+        .bind(1, 100)
+        .unwrap();
+        let subquery = FireSQLParser::parse("select :id from users where created > 100").unwrap();
+        assert_eq!(
+            result.conditions,
+            vec![
+                Condition::Comparison(
+                    field("userId"),
+                    CompareOperations::InSubquery(Box::new(subquery))
+                ),
+                Condition::Comparison(
+                    field("points"),
+                    CompareOperations::GreaterThan(Value::Integer(10))
+                ),
+            ]
+        );
+        assert!(result.unbound_parameters().is_empty());
+
+        for statement in [
+            "select a from b where c in (select * from d)",
+            "select a from b where c in (select e, f from d)",
+            "select a from b where c in (select count(*) from d)",
+            "select a from b where c in (select e from d group by e)",
+            "select a from b where c in (select e from d group by e having count(*) > 1)",
+        ] {
+            assert!(
+                matches!(
+                    FireSQLParser::parse(statement),
+                    Err(ParseError::InvalidSubquery(_))
+                ),
+                "{statement}"
+            );
+        }
+        assert!(matches!(
+            FireSQLParser::parse("select a from b where not c in (select e from d)"),
+            Err(ParseError::UnsupportedNegation(_))
+        ));
+    }
+
+    #[test]
+    fn set_operations() {
+        let result = FireSQLParser::parse(
+            r#"select name from users where age > $1
+            union all select name from admins limit 5
+            intersect select name from users where active = true
+            except select name from banned"#,
+        )
+        .unwrap()
+        .bind(1, 18)
+        .unwrap();
+        assert_eq!(result.collection.path, "users");
+        assert_eq!(
+            result
+                .set_operations
+                .iter()
+                .map(|(operator, select)| (*operator, select.collection.path.as_str()))
+                .collect_vec(),
+            vec![
+                (SetOperator::UnionAll, "admins"),
+                (SetOperator::Intersect, "users"),
+                (SetOperator::Except, "banned"),
+            ]
+        );
+        assert_eq!(result.set_operations[0].1.limit, Some(Limit::First(5)));
+        assert!(result.unbound_parameters().is_empty());
+
+        let result = FireSQLParser::parse("select * from users Union select * from admins u");
+        assert_eq!(result.unwrap().set_operations[0].0, SetOperator::Union);
+        let statements =
+            FireSQLParser::parse_script("select a from b union select c from d; select e from f")
+                .unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[0].span, 0..37);
+
+        let result = FireSQLParser::parse(
+            "select a from x limit 3 union (select a from y order by a limit 10)",
+        )
+        .unwrap();
+        assert_eq!(result.limit, Some(Limit::First(3)));
+        assert_eq!(result.set_operations[0].1.limit, Some(Limit::First(10)));
+        assert_eq!(result.set_operations[0].1.order_by.len(), 1);
+
+        for statement in [
+            "select a from x union select a from y order by a limit 10",
+            "select a from x except select a from y limit 10",
+            "select a from x intersect select a from y offset 5",
+            "select a from b union select c, d from e",
+            "select * from b except select c from e",
+            "select :id, * from b intersect select *, :id from e",
+        ] {
+            assert!(
+                matches!(
+                    FireSQLParser::parse(statement),
+                    Err(ParseError::InvalidSetOperation(_))
+                ),
+                "{statement}"
+            );
+        }
+    }
+
+    #[test]
+    fn not_prefixed_identifier() {
+        let result = FireSQLParser::parse("select a from b where notes = 1");
+        assert_eq!(
+            result.unwrap().conditions,
+            vec![Condition::Comparison(
+                field("notes"),
+                CompareOperations::Equal(Value::Integer(1))
+            )]
+        );
+
+        let result = FireSQLParser::parse(
+            "select a from b where not notes is null or origin = 1 and android = 2",
+        );
+        assert_eq!(
+            result.unwrap().conditions,
+            vec![Condition::Or(vec![
+                Condition::Not(Box::new(Condition::IsNull(field("notes")))),
+                Condition::And(vec![
+                    Condition::Comparison(
+                        field("origin"),
+                        CompareOperations::Equal(Value::Integer(1))
+                    ),
+                    Condition::Comparison(
+                        field("android"),
+                        CompareOperations::Equal(Value::Integer(2))
+                    ),
+                ]),
+            ])]
+        );
+        assert!(FireSQLParser::parse("select a from b where x = 1 ornot = 2").is_err());
+    }
 }
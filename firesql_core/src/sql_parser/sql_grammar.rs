@@ -61,4 +61,15 @@ mod test {
         );
         assert!(pairs.is_ok());
     }
+
+    #[test]
+    fn or_where_query() {
+        let pairs = FireSQLGrammarParser::parse(
+            Rule::select_stmt,
+            r#"select a from b
+                where a = 1 AND (b = 2 OR c > 3)
+                or (d = "x")"#,
+        );
+        assert!(pairs.is_ok());
+    }
 }
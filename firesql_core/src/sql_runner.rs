@@ -1,131 +1,963 @@
+mod grouping;
+mod joins;
+mod references;
+mod set_operations;
+#[cfg(test)]
+mod test_fixtures;
+mod values;
+
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
-use firestore::{async_trait, errors::FirestoreError, FirestoreDb, FirestoreValue};
-use futures::TryFutureExt as _;
+use firestore::{
+    async_trait,
+    errors::FirestoreError,
+    select_builder::{FirestoreSelectDocBuilder, FirestoreSelectInitialBuilder},
+    select_filter_builder::FirestoreQueryFilterBuilder,
+    FirestoreDb, FirestoreQueryCursor, FirestoreQueryDirection, FirestoreQueryFilter,
+    FirestoreReference, FirestoreValue,
+};
+use futures::{future::BoxFuture, FutureExt as _, StreamExt as _, TryStreamExt as _};
+use gcloud_sdk::google::firestore::v1::Document;
 use itertools::Itertools as _;
+use references::References;
+use values::{condition_matches, ValueKey};
+
+use crate::sql_parser::{
+    is_document_name, Aggregation, Collection, CompareOperations, Condition, Cursor, FieldPath,
+    FireSQLSelect, Limit, OrderBy, OrderDirection, Parameter, SelectProjection, Value,
+};
+
+#[derive(Debug)]
+pub enum ExecutionError {
+    Firestore(FirestoreError),
+    UnboundParameter(Parameter),
+    /// Query processed on the client side matched more documents than allowed.
+    TooManyDocuments(u32),
+    /// Chain of dereferenced fields is longer than allowed.
+    TooDeepDereference(u32),
+}
+
+impl core::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::Firestore(error) => write!(f, "Firestore error: {}", error),
+            ExecutionError::UnboundParameter(parameter) => {
+                write!(f, "Parameter {} is not bound", parameter)
+            }
+            ExecutionError::TooManyDocuments(limit) => write!(
+                f,
+                "Query matches more than {} documents that can be processed on the client side",
+                limit
+            ),
+            ExecutionError::TooDeepDereference(depth) => write!(
+                f,
+                "References can be dereferenced at most {} levels deep",
+                depth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExecutionError::Firestore(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<FirestoreError> for ExecutionError {
+    fn from(error: FirestoreError) -> Self {
+        ExecutionError::Firestore(error)
+    }
+}
+
+/// Maximum number of values in Firestore `IN` filter.
+const IN_VALUES_LIMIT: usize = 30;
+/// Maximum number of chunks of an `IN` list queried at the same time.
+const CONCURRENT_CHUNK_QUERIES: usize = 10;
+
+/// Settings of the statement execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionOptions {
+    max_fetched_documents: u32,
+    max_dereference_depth: u32,
+}
+
+impl ExecutionOptions {
+    /// Maximum number of documents fetched for processing on the client side
+    /// (e.g. for GROUP BY). Execution fails when the query matches more documents.
+    pub fn with_max_fetched_documents(self, max_fetched_documents: u32) -> Self {
+        Self {
+            max_fetched_documents,
+            ..self
+        }
+    }
+
+    /// Maximum number of chained dereferences, e.g. `author->manager->name` is 2 levels deep.
+    /// Every level is fetched with a separate batch of document reads.
+    pub fn with_max_dereference_depth(self, max_dereference_depth: u32) -> Self {
+        Self {
+            max_dereference_depth,
+            ..self
+        }
+    }
+}
 
-use crate::sql_parser::{FireSQLSelect, SelectProjection, Value};
+impl Default for ExecutionOptions {
+    fn default() -> Self {
+        Self {
+            max_fetched_documents: 10_000,
+            max_dereference_depth: 3,
+        }
+    }
+}
 
 #[async_trait]
 pub trait SQLExecutor {
     type Error;
-    async fn execute(self, select: FireSQLSelect) -> Result<Vec<Row>, Self::Error>;
+    async fn execute(self, select: FireSQLSelect) -> Result<Vec<Row>, Self::Error>
+    where
+        Self: Sized,
+    {
+        self.execute_with_options(select, ExecutionOptions::default())
+            .await
+    }
+
+    async fn execute_with_options(
+        self,
+        select: FireSQLSelect,
+        options: ExecutionOptions,
+    ) -> Result<Vec<Row>, Self::Error>;
 }
 
 #[async_trait]
 impl SQLExecutor for &FirestoreDb {
-    type Error = FirestoreError;
+    type Error = ExecutionError;
+
+    async fn execute_with_options(
+        self,
+        mut select: FireSQLSelect,
+        options: ExecutionOptions,
+    ) -> Result<Vec<Row>, Self::Error> {
+        if select.set_operations.is_empty() {
+            return select_rows(self, select, &options).await;
+        }
+        // combined statements are executed concurrently
+        let (operators, selects): (Vec<_>, Vec<_>) = std::mem::take(&mut select.set_operations)
+            .into_iter()
+            .unzip();
+        let selects = std::iter::once(select).chain(selects).collect_vec();
+        let by_path = set_operations::combined_by_path(&selects);
+        let names = selects[0]
+            .projections
+            .iter()
+            .map(SelectProjection::column_name)
+            .collect_vec();
+        let mut rows = futures::future::try_join_all(
+            selects
+                .into_iter()
+                .map(|select| set_operations::select_keyed_rows(self, select, &options, by_path)),
+        )
+        .await?
+        .into_iter();
+        let first = rows.next().unwrap_or_default();
+        Ok(set_operations::combine_rows(
+            &names,
+            first,
+            operators.into_iter().zip(rows).collect(),
+        ))
+    }
+}
+
+async fn select_rows(
+    db: &FirestoreDb,
+    select: FireSQLSelect,
+    options: &ExecutionOptions,
+) -> Result<Vec<Row>, ExecutionError> {
+    let projections = select.projections.clone();
+    match select_documents(db, select, options).await? {
+        Selected::Rows(rows) => Ok(rows
+            .iter()
+            .map(|values| values_row(&projections, values))
+            .collect()),
+        Selected::Documents(documents, references) => Ok(documents
+            .into_iter()
+            .map(|document| document_row(document, &projections, &references))
+            .collect()),
+    }
+}
 
-    async fn execute(self, select: FireSQLSelect) -> Result<Vec<Row>, Self::Error> {
-        let (collection, projections, conditions) =
-            (select.collection, select.projections, select.conditions);
-        let conditions = Box::new(conditions);
-        let query = self.fluent().select();
+/// Result of the statement - the selected documents or the projected values
+/// of groups and aggregations.
+enum Selected {
+    Rows(Vec<Vec<Option<gcloud_sdk::google::firestore::v1::Value>>>),
+    Documents(Vec<Document>, References),
+}
+
+async fn select_documents(
+    db: &FirestoreDb,
+    mut select: FireSQLSelect,
+    options: &ExecutionOptions,
+) -> Result<Selected, ExecutionError> {
+    if let Some(parameter) = select.unbound_parameters().into_iter().next() {
+        return Err(ExecutionError::UnboundParameter(parameter));
+    }
+    resolve_references(&mut select, db.get_documents_path());
+    resolve_subqueries(db, &mut select, options).await?;
+    // `IN` lists of subquery values longer than Firestore allows are queried in chunks
+    let (chunked, matches_nothing) = plan_in_lists(&mut select);
+    let (collection, projections, conditions, order_by) = (
+        select.collection,
+        select.projections,
+        select.conditions,
+        select.order_by,
+    );
+    // Firestore has no "limit to last" - the query is run in reversed order
+    // and the results are reversed back afterwards
+    let reversed = matches!(select.limit, Some(Limit::Last(_)));
+    let conditions = Box::new(conditions);
+    let query = db.fluent().select();
 
-        let query = if !projections.contains(&SelectProjection::Object) {
-            query.fields(projections.iter().filter_map(|field| match field {
-                SelectProjection::Property(name) => Some(name),
+    // joined documents and conditions on referenced documents are checked
+    // on the client side
+    let joined = !select.joins.is_empty();
+    let client_filtered =
+        joined || !select.client_conditions.is_empty() || chunked.is_some() || matches_nothing;
+    let dereferenced = references::dereferenced_fields(&projections, &select.client_conditions);
+    let max_dereference_depth = options.max_dereference_depth;
+    if dereferenced
+        .iter()
+        .any(|chain| chain.len() > max_dereference_depth as usize)
+    {
+        return Err(ExecutionError::TooDeepDereference(max_dereference_depth));
+    }
+    // GROUP BY, HAVING and aggregations not supported by Firestore are calculated
+    // on the client side
+    let client_aggregated = |projection: &SelectProjection| match projection {
+        SelectProjection::Aggregate(aggregation, _) => {
+            !aggregation.is_server_side() || client_filtered
+        }
+        _ => false,
+    };
+    let grouped = !select.group_by.is_empty()
+        || select.having.is_some()
+        || projections.iter().any(client_aggregated);
+    let aggregated =
+        !grouped && matches!(projections.first(), Some(SelectProjection::Aggregate(..)));
+    let query = if client_filtered {
+        query
+    } else if grouped {
+        let aggregated_fields = projections
+            .iter()
+            .filter_map(|projection| match projection {
+                SelectProjection::Aggregate(aggregation, _) => aggregation.field(),
                 _ => None,
-            }))
-        } else {
-            query
-        };
-        let collection_path_segments: Vec<&str> = collection.path.split("/").collect_vec();
+            });
+        query.fields(
+            select
+                .group_by
+                .iter()
+                .chain(aggregated_fields)
+                .unique()
+                .map(FieldPath::to_string),
+        )
+    } else if !aggregated && !projections.contains(&SelectProjection::Object) {
+        query.fields(projections.iter().filter_map(|field| match field {
+            SelectProjection::Property(path, _) => Some(path.to_string()),
+            SelectProjection::Dereference(paths, _) => Some(paths[0].to_string()),
+            _ => None,
+        }))
+    } else {
+        query
+    };
+    let (query, documents_path) = from_collection(db, query, &collection)?;
 
-        let parent = if collection_path_segments.len() > 2 {
-            let chunks = collection_path_segments.iter().chunks(2);
-            let mut parent_documents = chunks
-                .into_iter()
-                .filter_map(|mut pair| pair.next_tuple::<(_, _)>());
-            let (first_collection_name, first_document_id) = parent_documents
-                .next()
-                .expect("First document path should be always present");
-            let mut path_builder = self.parent_path(first_collection_name, first_document_id)?;
-            for (collection_name, document_id) in parent_documents {
-                path_builder = path_builder.at(collection_name, document_id)?;
-            }
-            Some(path_builder)
-        } else {
-            None
-        };
-        let target_collection = collection_path_segments
-            .last()
-            .expect("Collection is missing");
-        let query = query.from(*target_collection);
-        let query = match parent {
-            Some(parent) => query.parent(parent),
-            None => query,
-        };
+    let query = query.filter(|f| f.for_all(conditions.iter().map(|c| condition_filter(&f, c))));
+    let query = if !order_by.is_empty() {
+        query.order_by(order_by.iter().map(|order| {
+            let direction = match (order.direction, reversed) {
+                (OrderDirection::Ascending, false) | (OrderDirection::Descending, true) => {
+                    FirestoreQueryDirection::Ascending
+                }
+                (OrderDirection::Descending, false) | (OrderDirection::Ascending, true) => {
+                    FirestoreQueryDirection::Descending
+                }
+            };
+            (order.field.to_string(), direction)
+        }))
+    } else {
+        query
+    };
+    let cursor_values = |cursor: &Cursor| {
+        cursor
+            .values
+            .iter()
+            .zip(order_by.iter())
+            .map(|(value, order)| match value {
+                // cursor on document id needs the full document path
+                Value::String(id) if order.field.is_document_id() => {
+                    FirestoreValue::from(gcloud_sdk::google::firestore::v1::Value {
+                        value_type: Some(
+                            gcloud_sdk::google::firestore::v1::value::ValueType::ReferenceValue(
+                                format!("{documents_path}/{id}"),
+                            ),
+                        ),
+                    })
+                }
+                value => ValueWrapper::from(value).into(),
+            })
+            .collect_vec()
+    };
+    let start_cursor = |cursor: &Cursor| match cursor.inclusive {
+        true => FirestoreQueryCursor::BeforeValue(cursor_values(cursor)),
+        false => FirestoreQueryCursor::AfterValue(cursor_values(cursor)),
+    };
+    let end_cursor = |cursor: &Cursor| match cursor.inclusive {
+        true => FirestoreQueryCursor::AfterValue(cursor_values(cursor)),
+        false => FirestoreQueryCursor::BeforeValue(cursor_values(cursor)),
+    };
+    // in reversed query the cursors swap their roles
+    let (start_at, end_at) = match reversed {
+        false => (select.start_at, select.end_at),
+        true => (select.end_at, select.start_at),
+    };
+    let query = match start_at {
+        Some(cursor) => query.start_at(start_cursor(&cursor)),
+        None => query,
+    };
+    let query = match end_at {
+        Some(cursor) => query.end_at(end_cursor(&cursor)),
+        None => query,
+    };
+    if aggregated {
+        // the limit and the offset apply to the single row of the aggregations
+        let rows = aggregate(query, &conditions, &projections).await?;
+        return Ok(Selected::Rows(apply_limit(
+            rows,
+            select.limit,
+            select.offset,
+        )));
+    }
+
+    // one document more than allowed is enough to tell that the query matches too many
+    let max_fetched_documents = options.max_fetched_documents;
+    let fetch_limit = max_fetched_documents.saturating_add(1);
+    // chunks of the `IN` list are merged, sorted and limited afterwards
+    let chunked_only = chunked.is_some()
+        && !joined
+        && select.client_conditions.is_empty()
+        && !grouped
+        && !select.distinct;
+    let (limit, offset) = query_limit(
+        select.limit,
+        select.offset,
+        client_filtered || grouped || select.distinct,
+        chunked_only,
+        fetch_limit,
+    );
+    let limited_chunks = chunked_only && select.limit.is_some();
+    let query = match limit {
+        Some(limit) => query.limit(limit),
+        None => query,
+    };
+    let query = match offset {
+        Some(offset) => query.offset(offset),
+        None => query,
+    };
+
+    let mut documents = match chunked {
+        _ if matches_nothing => vec![],
+        Some(index) => query_chunks(query, &conditions, index).await?,
+        None => query.query().await?,
+    };
+    if reversed {
+        documents.reverse();
+    }
+    if chunked.is_some() {
+        sort_documents(&mut documents, &order_by);
+    }
+
+    if client_filtered && !limited_chunks && documents.len() > max_fetched_documents as usize {
+        return Err(ExecutionError::TooManyDocuments(max_fetched_documents));
+    }
+    if joined {
+        let alias = select
+            .collection_alias
+            .as_deref()
+            .expect("main collection alias of joined collections");
+        documents = joins::join_documents(db, documents, alias, &select.joins).await?;
+    }
+    let references = References::fetch(db, &documents, &dereferenced).await?;
+    if client_filtered {
+        documents.retain(|document| {
+            select
+                .client_conditions
+                .iter()
+                .all(|condition| condition_matches(&document.fields, condition, &references))
+        });
+        if !select.distinct && !grouped {
+            documents = apply_limit(documents, select.limit, select.offset);
+        }
+    }
+
+    if grouped {
+        if !client_filtered && documents.len() > max_fetched_documents as usize {
+            return Err(ExecutionError::TooManyDocuments(max_fetched_documents));
+        }
+        let rows = grouping::group_documents(
+            &documents,
+            &projections,
+            &select.group_by,
+            select.having.as_ref(),
+        );
+        return Ok(Selected::Rows(apply_limit(
+            rows,
+            select.limit,
+            select.offset,
+        )));
+    }
 
-        let query = query.filter(|f| {
-            let c = conditions.iter().map(|c| match c {
-                crate::sql_parser::Condition::Not(_condition) => {
-                    todo!("Condition negation not implemented")
+    if select.distinct {
+        if !client_filtered && documents.len() > max_fetched_documents as usize {
+            return Err(ExecutionError::TooManyDocuments(max_fetched_documents));
+        }
+        documents = grouping::distinct_documents(documents, &projections, &references);
+        documents = apply_limit(documents, select.limit, select.offset);
+    }
+
+    Ok(Selected::Documents(documents, references))
+}
+
+fn document_row(d: Document, projections: &[SelectProjection], references: &References) -> Row {
+    let columns = projections
+        .iter()
+        .map(|projection| {
+            let value = match projection {
+                SelectProjection::ObjectId(_) => d.name.split("/").last().unwrap().to_owned(),
+                SelectProjection::Object => {
+                    let fields = d
+                        .fields
+                        .iter()
+                        .map(|(key, value)| (key, firestore_value_to_string(value)))
+                        .collect::<HashMap<_, _>>();
+                    serde_json::to_string(&fields).unwrap()
                 }
-                crate::sql_parser::Condition::IsNull(field) => f.field(field).is_null(),
-                crate::sql_parser::Condition::Comparison(field, compare_operations) => {
-                    let field = f.field(field);
-                    match compare_operations {
-                        crate::sql_parser::CompareOperations::Equal(value) => {
-                            field.eq(ValueWrapper::from(value))
-                        }
-                        crate::sql_parser::CompareOperations::NotEqual(value) => {
-                            field.not_equal(ValueWrapper::from(value))
-                        }
-                        crate::sql_parser::CompareOperations::GreaterThan(value) => {
-                            field.greater_than(ValueWrapper::from(value))
-                        }
-                        crate::sql_parser::CompareOperations::LessThan(value) => {
-                            field.less_than(ValueWrapper::from(value))
-                        }
-                    }
+                SelectProjection::Property(..) | SelectProjection::Dereference(..) => {
+                    projection_value(&d, projection, references)
+                        .map(firestore_value_to_string)
+                        .unwrap_or_else(|| "nil".to_owned())
                 }
-            });
+                SelectProjection::Aggregate(..) => {
+                    unreachable!("aggregations are run separately")
+                }
+            };
+            (projection.column_name(), value)
+        })
+        .collect_vec();
+    Row(d.name, columns)
+}
+
+/// Row of the projected values of groups and aggregations.
+fn values_row(
+    projections: &[SelectProjection],
+    values: &[Option<gcloud_sdk::google::firestore::v1::Value>],
+) -> Row {
+    let columns = projections
+        .iter()
+        .zip(values)
+        .map(|(projection, value)| {
+            let value = value
+                .as_ref()
+                .map(firestore_value_to_string)
+                .unwrap_or_else(|| "nil".to_owned());
+            (projection.column_name(), value)
+        })
+        .collect();
+    Row(String::new(), columns)
+}
+
+/// Typed value of the column of the document row. The document id is a string
+/// and the whole document (`*`) is a map of its fields.
+fn column_value(
+    document: &Document,
+    projection: &SelectProjection,
+    references: &References,
+) -> Option<gcloud_sdk::google::firestore::v1::Value> {
+    let value_type = match projection {
+        SelectProjection::ObjectId(_) => {
+            gcloud_sdk::google::firestore::v1::value::ValueType::StringValue(
+                document
+                    .name
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_owned(),
+            )
+        }
+        SelectProjection::Object => gcloud_sdk::google::firestore::v1::value::ValueType::MapValue(
+            gcloud_sdk::google::firestore::v1::MapValue {
+                fields: document.fields.clone(),
+            },
+        ),
+        projection => return projection_value(document, projection, references).cloned(),
+    };
+    Some(gcloud_sdk::google::firestore::v1::Value {
+        value_type: Some(value_type),
+    })
+}
+
+/// Value of the projected document field (or of the document id).
+fn projection_value<'a>(
+    document: &'a Document,
+    projection: &SelectProjection,
+    references: &'a References,
+) -> Option<&'a gcloud_sdk::google::firestore::v1::Value> {
+    match projection {
+        SelectProjection::Property(field, _) => field_value(&document.fields, field),
+        SelectProjection::Dereference(paths, _) => references.value(&document.fields, paths),
+        _ => None,
+    }
+}
 
-            f.for_all(c)
+/// Runs the subqueries of `IN (SELECT ...)` conditions and replaces them with the lists
+/// of the selected values.
+fn resolve_subqueries<'a>(
+    db: &'a FirestoreDb,
+    select: &'a mut FireSQLSelect,
+    options: &'a ExecutionOptions,
+) -> BoxFuture<'a, Result<(), ExecutionError>> {
+    async move {
+        let mut subqueries = vec![];
+        select.visit_operations_mut(&mut |operation| {
+            if let CompareOperations::InSubquery(subquery) = operation {
+                subqueries.push(subquery_values(db, (**subquery).clone(), options));
+            }
         });
+        let mut values = futures::future::try_join_all(subqueries).await?.into_iter();
+        select.visit_operations_mut(&mut |operation| {
+            if let CompareOperations::InSubquery(_) = operation {
+                *operation = CompareOperations::In(values.next().expect("subquery values"));
+            }
+        });
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Distinct non-null values of the single column selected by the subquery.
+/// Subquery selecting more documents than allowed fails.
+async fn subquery_values(
+    db: &FirestoreDb,
+    mut select: FireSQLSelect,
+    options: &ExecutionOptions,
+) -> Result<Vec<Value>, ExecutionError> {
+    let projection = select.projections[0].clone();
+    // one document more than allowed is enough to tell that the subquery selects too many
+    let max_fetched_documents = options.max_fetched_documents;
+    let fetch_limit = max_fetched_documents.saturating_add(1);
+    select.limit = Some(match select.limit {
+        Some(Limit::First(limit)) => Limit::First(limit.min(fetch_limit)),
+        Some(Limit::Last(limit)) => Limit::Last(limit.min(fetch_limit)),
+        None => Limit::First(fetch_limit),
+    });
+    let (documents, references) = match select_documents(db, select, options).await? {
+        Selected::Documents(documents, references) => (documents, references),
+        Selected::Rows(_) => unreachable!("subqueries select document fields"),
+    };
+    if documents.len() > max_fetched_documents as usize {
+        return Err(ExecutionError::TooManyDocuments(max_fetched_documents));
+    }
+    Ok(documents
+        .iter()
+        .filter_map(|document| column_value(document, &projection, &references))
+        .filter(|value| !values::is_null(value))
+        .unique_by(|value| ValueKey(value.clone()))
+        .map(|value| values::statement_value(&value))
+        .collect())
+}
+
+/// Prepares `IN` lists of subquery values for the Firestore query, which rejects empty
+/// lists and lists longer than `IN_VALUES_LIMIT`. Empty lists match nothing. The first
+/// long list of the top-level conditions is queried in chunks, other conditions with
+/// long lists are checked on the client side. Returns the index of the chunked condition
+/// and whether the conditions match no documents at all.
+fn plan_in_lists(select: &mut FireSQLSelect) -> (Option<usize>, bool) {
+    let Some(conditions) = std::mem::take(&mut select.conditions)
+        .into_iter()
+        .map(without_empty_lists)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return (None, true);
+    };
+    let mut chunked = None;
+    for condition in conditions {
+        let chunkable = matches!(
+            &condition,
+            Condition::Comparison(_, CompareOperations::In(values)) if values.len() > IN_VALUES_LIMIT
+        );
+        if chunkable && chunked.is_none() {
+            chunked = Some(select.conditions.len());
+            select.conditions.push(condition);
+        } else if has_long_list(&condition) {
+            // client-side conditions of statements with joins start with the collection alias
+            let condition = match &select.collection_alias {
+                Some(alias) => condition.map_field_paths(&|field| {
+                    FieldPath(std::iter::once(alias.clone()).chain(field.0).collect())
+                }),
+                None => condition,
+            };
+            select.client_conditions.push(condition);
+        } else {
+            select.conditions.push(condition);
+        }
+    }
+    (chunked, false)
+}
+
+/// Condition without the alternatives with empty `IN` lists, `None` if the condition
+/// matches nothing.
+fn without_empty_lists(condition: Condition) -> Option<Condition> {
+    match condition {
+        Condition::Comparison(_, CompareOperations::In(values)) if values.is_empty() => None,
+        Condition::And(conditions) => conditions
+            .into_iter()
+            .map(without_empty_lists)
+            .collect::<Option<Vec<_>>>()
+            .map(Condition::And),
+        Condition::Or(conditions) => {
+            let mut conditions = conditions
+                .into_iter()
+                .filter_map(without_empty_lists)
+                .collect_vec();
+            match conditions.len() {
+                0 => None,
+                1 => conditions.pop(),
+                _ => Some(Condition::Or(conditions)),
+            }
+        }
+        condition => Some(condition),
+    }
+}
+
+fn has_long_list(condition: &Condition) -> bool {
+    match condition {
+        Condition::And(conditions) | Condition::Or(conditions) => {
+            conditions.iter().any(has_long_list)
+        }
+        Condition::Not(condition) | Condition::Dereference(_, condition) => {
+            has_long_list(condition)
+        }
+        Condition::IsNull(_) => false,
+        Condition::Comparison(_, operation) => matches!(
+            operation,
+            CompareOperations::In(values) if values.len() > IN_VALUES_LIMIT
+        ),
+    }
+}
+
+/// Runs the query for every chunk of the long `IN` list of the condition
+/// and merges the results.
+async fn query_chunks(
+    query: FirestoreSelectDocBuilder<'_, FirestoreDb>,
+    conditions: &[Condition],
+    index: usize,
+) -> Result<Vec<Document>, ExecutionError> {
+    let Condition::Comparison(field, CompareOperations::In(values)) = &conditions[index] else {
+        unreachable!("only IN lists are queried in chunks")
+    };
+    let queries = values
+        .chunks(IN_VALUES_LIMIT)
+        .map(|chunk| {
+            let mut conditions = conditions.to_vec();
+            conditions[index] =
+                Condition::Comparison(field.clone(), CompareOperations::In(chunk.to_vec()));
+            query
+                .clone()
+                .filter(|f| f.for_all(conditions.iter().map(|c| condition_filter(&f, c))))
+                .query()
+        })
+        .collect_vec();
+    Ok(futures::stream::iter(queries)
+        .buffered(CONCURRENT_CHUNK_QUERIES)
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// Sorts documents merged from several queries the way Firestore orders query results.
+fn sort_documents(documents: &mut [Document], order_by: &[OrderBy]) {
+    let document_id_direction = order_by
+        .last()
+        .map(|order| order.direction)
+        .unwrap_or(OrderDirection::Ascending);
+    let directed = |ordering: Ordering, direction| match direction {
+        OrderDirection::Ascending => ordering,
+        OrderDirection::Descending => ordering.reverse(),
+    };
+    documents.sort_by(|a, b| {
+        let compare_names = || a.name.split('/').cmp(b.name.split('/'));
+        order_by
+            .iter()
+            .map(|order| {
+                let ordering = match order.field.is_document_id() {
+                    true => compare_names(),
+                    false => match (
+                        field_value(&a.fields, &order.field),
+                        field_value(&b.fields, &order.field),
+                    ) {
+                        (Some(a), Some(b)) => values::compare_values(a, b),
+                        (a, b) => a.is_some().cmp(&b.is_some()),
+                    },
+                };
+                directed(ordering, order.direction)
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| directed(compare_names(), document_id_direction))
+    });
+}
+
+/// Limit and offset of the Firestore query. Documents processed on the client side
+/// are fetched up to the fetch limit, as the limit and the offset apply to the rows
+/// of the statement: the filtered documents, the groups or the distinct rows.
+/// When chunking is the only client-side step, every chunk needs at most the documents
+/// of the limit and the offset.
+fn query_limit(
+    limit: Option<Limit>,
+    offset: Option<u32>,
+    client_side: bool,
+    chunked_only: bool,
+    fetch_limit: u32,
+) -> (Option<u32>, Option<u32>) {
+    let limit = match limit {
+        Some(Limit::First(limit)) | Some(Limit::Last(limit)) => Some(limit),
+        None => None,
+    };
+    match (limit, chunked_only) {
+        (Some(limit), true) => (Some(limit.saturating_add(offset.unwrap_or_default())), None),
+        _ if client_side => (Some(fetch_limit), None),
+        _ => (limit, offset),
+    }
+}
+
+/// Applies the limit and the offset to the documents processed on the client side.
+fn apply_limit<T>(documents: Vec<T>, limit: Option<Limit>, offset: Option<u32>) -> Vec<T> {
+    let offset = offset.unwrap_or_default() as usize;
+    match limit {
+        Some(Limit::First(limit)) => documents
+            .into_iter()
+            .skip(offset)
+            .take(limit as usize)
+            .collect(),
+        Some(Limit::Last(limit)) => {
+            let skipped = documents.len().saturating_sub(limit as usize);
+            documents.into_iter().skip(skipped).collect()
+        }
+        None => documents.into_iter().skip(offset).collect(),
+    }
+}
+
+/// Targets the query at the collection (or collection group). Returns the query
+/// together with the path that document ids of the collection are relative to.
+fn from_collection<'a>(
+    db: &'a FirestoreDb,
+    query: FirestoreSelectInitialBuilder<'a, FirestoreDb>,
+    collection: &Collection,
+) -> Result<(FirestoreSelectDocBuilder<'a, FirestoreDb>, String), FirestoreError> {
+    let collection_path_segments: Vec<&str> = collection.path.split("/").collect_vec();
+
+    let parent = if collection_path_segments.len() > 2 {
+        let chunks = collection_path_segments.iter().chunks(2);
+        let mut parent_documents = chunks
+            .into_iter()
+            .filter_map(|mut pair| pair.next_tuple::<(_, _)>());
+        let (first_collection_name, first_document_id) = parent_documents
+            .next()
+            .expect("First document path should be always present");
+        let mut path_builder = db.parent_path(first_collection_name, first_document_id)?;
+        for (collection_name, document_id) in parent_documents {
+            path_builder = path_builder.at(collection_name, document_id)?;
+        }
+        Some(path_builder)
+    } else {
+        None
+    };
+    let target_collection = collection_path_segments
+        .last()
+        .expect("Collection is missing");
+    let query = query.from(*target_collection);
+    // document ids in collection group are only unique together with their parent,
+    // so they are expected to be paths relative to the database root
+    let documents_path = match collection.all_descendants {
+        true => db.get_documents_path().clone(),
+        false => format!(
+            "{}/{}",
+            parent
+                .as_ref()
+                .map(|parent| parent.as_ref())
+                .unwrap_or(db.get_documents_path()),
+            target_collection
+        ),
+    };
+    let query = match parent {
+        Some(parent) => query.parent(parent),
+        None => query,
+    };
+    let query = match collection.all_descendants {
+        true => query.all_descendants(),
+        false => query,
+    };
+
+    Ok((query, documents_path))
+}
 
-        let results = query
-            .query()
-            .map_ok(|results| {
-                results
-                    .into_iter()
-                    .map(|d| {
-                        let columns = projections
+/// Runs the aggregations as Firestore aggregation queries and returns their values
+/// as a single row.
+/// `COUNT(field)` needs an additional filter, so it is calculated in a separate query.
+async fn aggregate(
+    query: FirestoreSelectDocBuilder<'_, FirestoreDb>,
+    conditions: &[Condition],
+    projections: &[SelectProjection],
+) -> Result<Vec<Vec<Option<gcloud_sdk::google::firestore::v1::Value>>>, ExecutionError> {
+    let aggregation_alias = |index: usize| format!("aggregation_{index}");
+    let aggregations = projections
+        .iter()
+        .enumerate()
+        .filter_map(|(index, projection)| match projection {
+            SelectProjection::Aggregate(aggregation, _) => {
+                Some((aggregation_alias(index), aggregation))
+            }
+            _ => None,
+        })
+        .into_group_map_by(|(_, aggregation)| match aggregation {
+            Aggregation::Count(field) => Some(field.clone()),
+            _ => None,
+        });
+    let queries =
+        aggregations
+            .into_iter()
+            .map(|(counted_field, aggregations)| {
+                let query = match counted_field {
+                    Some(field) => {
+                        let conditions = conditions
                             .iter()
-                            .map(|projection| match projection {
-                                SelectProjection::ObjectId => (
-                                    ":id".to_owned(),
-                                    d.name.split("/").last().unwrap().to_owned(),
-                                ),
-                                SelectProjection::Object => {
-                                    let fields = d
-                                        .fields
-                                        .iter()
-                                        .map(|(key, value)| (key, firestore_value_to_string(value)))
-                                        .collect::<HashMap<_, _>>();
-                                    ("*".to_owned(), serde_json::to_string(&fields).unwrap())
-                                }
-                                SelectProjection::Property(property) => (
-                                    property.clone(),
-                                    d.fields
-                                        .get(property)
-                                        .map(firestore_value_to_string)
-                                        .unwrap_or_else(|| "nil".to_owned()),
-                                ),
-                            })
+                            .cloned()
+                            .chain(std::iter::once(Condition::Not(Box::new(
+                                Condition::IsNull(field),
+                            ))))
                             .collect_vec();
-                        Row(d.name, columns)
+                        query.clone().filter(|f| {
+                            f.for_all(conditions.iter().map(|c| condition_filter(&f, c)))
+                        })
+                    }
+                    None => query.clone(),
+                };
+                query
+                    .aggregate(|a| {
+                        a.fields(aggregations.iter().map(
+                            |(alias, aggregation)| match aggregation {
+                                Aggregation::CountAll | Aggregation::Count(_) => {
+                                    a.field(alias).count()
+                                }
+                                Aggregation::Sum(field) => a.field(alias).sum(field.to_string()),
+                                Aggregation::Avg(field) => a.field(alias).avg(field.to_string()),
+                                Aggregation::Min(_) | Aggregation::Max(_) => {
+                                    unreachable!("MIN and MAX are calculated on the client side")
+                                }
+                            },
+                        ))
                     })
-                    .collect_vec()
-            })
-            .await?;
+                    .query()
+            });
+    let values = futures::future::try_join_all(queries)
+        .await?
+        .into_iter()
+        .flatten()
+        .flat_map(|document| document.fields)
+        .collect::<HashMap<_, _>>();
+    Ok(vec![(0..projections.len())
+        .map(|index| values.get(&aggregation_alias(index)).cloned())
+        .collect()])
+}
 
-        // todo: convert result documents into row
-        Ok(results)
+fn condition_filter(
+    f: &FirestoreQueryFilterBuilder,
+    condition: &Condition,
+) -> Option<FirestoreQueryFilter> {
+    match condition {
+        Condition::And(conditions) => f.for_all(conditions.iter().map(|c| condition_filter(f, c))),
+        Condition::Or(conditions) => f.for_any(conditions.iter().map(|c| condition_filter(f, c))),
+        Condition::Not(condition) => match condition.as_ref() {
+            Condition::IsNull(field) => f.field(field.to_string()).is_not_null(),
+            condition => unreachable!("negation of {condition:?} is rewritten by the parser"),
+        },
+        Condition::IsNull(field) => f.field(field.to_string()).is_null(),
+        Condition::Dereference(..) => {
+            unreachable!("conditions on referenced documents are checked on the client side")
+        }
+        Condition::Comparison(_, CompareOperations::InSubquery(_)) => {
+            unreachable!("subqueries are run before the query")
+        }
+        Condition::Comparison(field, compare_operations) => {
+            let field = f.field(field.to_string());
+            match compare_operations {
+                CompareOperations::Equal(value) => field.eq(ValueWrapper::from(value)),
+                CompareOperations::NotEqual(value) => field.not_equal(ValueWrapper::from(value)),
+                CompareOperations::GreaterThan(value) => {
+                    field.greater_than(ValueWrapper::from(value))
+                }
+                CompareOperations::GreaterThanOrEqual(value) => {
+                    field.greater_than_or_equal(ValueWrapper::from(value))
+                }
+                CompareOperations::LessThan(value) => field.less_than(ValueWrapper::from(value)),
+                CompareOperations::LessThanOrEqual(value) => {
+                    field.less_than_or_equal(ValueWrapper::from(value))
+                }
+                CompareOperations::In(values) => field.is_in(ValueListWrapper(values)),
+                CompareOperations::NotIn(values) => field.is_not_in(ValueListWrapper(values)),
+                CompareOperations::ArrayContains(value) => {
+                    field.array_contains(ValueWrapper::from(value))
+                }
+                CompareOperations::ArrayContainsAny(values) => {
+                    field.array_contains_any(ValueListWrapper(values))
+                }
+                CompareOperations::InSubquery(_) => unreachable!(),
+            }
+        }
     }
 }
 
+/// Turns document paths of `ref(...)` literals into full document names.
+fn resolve_references(select: &mut FireSQLSelect, documents_path: &str) {
+    select.visit_values_mut(&mut |value| {
+        if let Value::Reference(path) = value {
+            if !is_document_name(path) {
+                *path = format!("{}/{}", documents_path, path);
+            }
+        }
+    });
+}
+
+/// Looks up the value of a (possibly nested) field in document fields.
+fn field_value<'a>(
+    fields: &'a HashMap<String, gcloud_sdk::google::firestore::v1::Value>,
+    path: &FieldPath,
+) -> Option<&'a gcloud_sdk::google::firestore::v1::Value> {
+    let (first, rest) = path.0.split_first()?;
+    rest.iter().try_fold(fields.get(first)?, |value, segment| {
+        match value.value_type.as_ref()? {
+            gcloud_sdk::google::firestore::v1::value::ValueType::MapValue(map_value) => {
+                map_value.fields.get(segment)
+            }
+            _ => None,
+        }
+    })
+}
+
 struct ValueWrapper<'a>(&'a Value);
 
 impl<'a> From<&'a Value> for ValueWrapper<'a> {
@@ -137,14 +969,73 @@ impl<'a> From<&'a Value> for ValueWrapper<'a> {
 impl<'a> From<ValueWrapper<'a>> for FirestoreValue {
     fn from(val: ValueWrapper<'a>) -> Self {
         match &val.0 {
-            Value::Number(n) => n.into(),
+            Value::Integer(n) => n.into(),
+            Value::Double(n) => n.into(),
             Value::String(s) => s.into(),
             Value::Bool(b) => b.into(),
-            Value::Reference(path) => path.into(),
+            Value::Reference(name) => FirestoreReference(name.clone()).into(),
+            Value::Timestamp(seconds, nanos) => {
+                FirestoreValue::from(gcloud_sdk::google::firestore::v1::Value {
+                    value_type: Some(
+                        gcloud_sdk::google::firestore::v1::value::ValueType::TimestampValue(
+                            gcloud_sdk::prost_types::Timestamp {
+                                seconds: *seconds,
+                                nanos: *nanos,
+                            },
+                        ),
+                    ),
+                })
+            }
+            Value::GeoPoint(latitude, longitude) => {
+                FirestoreValue::from(gcloud_sdk::google::firestore::v1::Value {
+                    value_type: Some(
+                        gcloud_sdk::google::firestore::v1::value::ValueType::GeoPointValue(
+                            gcloud_sdk::google::r#type::LatLng {
+                                latitude: *latitude,
+                                longitude: *longitude,
+                            },
+                        ),
+                    ),
+                })
+            }
+            Value::Bytes(bytes) => FirestoreValue::from(gcloud_sdk::google::firestore::v1::Value {
+                value_type: Some(
+                    gcloud_sdk::google::firestore::v1::value::ValueType::BytesValue(bytes.clone()),
+                ),
+            }),
+            Value::Null => FirestoreValue::from(gcloud_sdk::google::firestore::v1::Value {
+                value_type: Some(gcloud_sdk::google::firestore::v1::value::ValueType::NullValue(0)),
+            }),
+            Value::Array(values) => ValueListWrapper(values).into(),
+            Value::Parameter(parameter) => {
+                unreachable!("parameter {parameter} should be bound before execution")
+            }
+            Value::Map(entries) => FirestoreValue::from_map(entries.iter().map(|(key, value)| {
+                (key, Into::<FirestoreValue>::into(ValueWrapper::from(value)))
+            })),
         }
     }
 }
 
+struct ValueListWrapper<'a>(&'a [Value]);
+
+impl<'a> From<ValueListWrapper<'a>> for FirestoreValue {
+    fn from(val: ValueListWrapper<'a>) -> Self {
+        let values = val
+            .0
+            .iter()
+            .map(|value| Into::<FirestoreValue>::into(ValueWrapper::from(value)).value)
+            .collect();
+        FirestoreValue::from(gcloud_sdk::google::firestore::v1::Value {
+            value_type: Some(
+                gcloud_sdk::google::firestore::v1::value::ValueType::ArrayValue(
+                    gcloud_sdk::google::firestore::v1::ArrayValue { values },
+                ),
+            ),
+        })
+    }
+}
+
 fn firestore_value_to_string(v: &gcloud_sdk::google::firestore::v1::Value) -> String {
     let vt = v
         .value_type
@@ -159,7 +1050,9 @@ fn firestore_value_to_string(v: &gcloud_sdk::google::firestore::v1::Value) -> St
             format!("{timestamp}")
         }
         gcloud_sdk::google::firestore::v1::value::ValueType::StringValue(v) => v.to_string(),
-        gcloud_sdk::google::firestore::v1::value::ValueType::BytesValue(_vec) => todo!(),
+        gcloud_sdk::google::firestore::v1::value::ValueType::BytesValue(bytes) => {
+            format!("x'{}'", bytes.iter().map(|b| format!("{b:02x}")).join(""))
+        }
         gcloud_sdk::google::firestore::v1::value::ValueType::ReferenceValue(r) => format!("#:{r}"),
         gcloud_sdk::google::firestore::v1::value::ValueType::GeoPointValue(lat_lng) => {
             format!("{}:{}", lat_lng.latitude, lat_lng.longitude)
@@ -188,3 +1081,112 @@ impl Row {
         &self.1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parser::FireSQLParser;
+
+    #[test]
+    fn query_limits() {
+        let select = FireSQLParser::parse("select a from b limit 3 offset 2").unwrap();
+        assert_eq!(
+            query_limit(select.limit, select.offset, false, false, 101),
+            (Some(3), Some(2))
+        );
+        // grouped, distinct and filtered rows are limited after the documents are fetched
+        assert_eq!(
+            query_limit(select.limit, select.offset, true, false, 101),
+            (Some(101), None)
+        );
+        assert_eq!(
+            query_limit(select.limit, select.offset, true, true, 101),
+            (Some(5), None)
+        );
+        assert_eq!(query_limit(None, None, true, true, 101), (Some(101), None));
+    }
+
+    #[test]
+    fn planned_in_lists() {
+        let planned = |sql: &str, lengths: [usize; 3]| {
+            let mut select = FireSQLParser::parse(sql).unwrap();
+            // lists of subquery values replace the lists (1), (2) and (3)
+            select.visit_operations_mut(&mut |operation| {
+                if let CompareOperations::In(values) = operation {
+                    let Value::Integer(index) = values[0] else {
+                        unreachable!()
+                    };
+                    *values = vec![values[0].clone(); lengths[index as usize - 1]];
+                }
+            });
+            let plan = plan_in_lists(&mut select);
+            (plan, select)
+        };
+        let list = |field: &str, value, length| {
+            Condition::Comparison(
+                FieldPath(field.split('.').map(str::to_owned).collect()),
+                CompareOperations::In(vec![Value::Integer(value); length]),
+            )
+        };
+        let sql = "select a from b where c in (1) and (d = 2 or e in (2)) and f in (3)";
+
+        let ((chunked, matches_nothing), select) = planned(sql, [40, 40, 40]);
+        assert_eq!((chunked, matches_nothing), (Some(0), false));
+        assert_eq!(select.conditions, vec![list("c", 1, 40)]);
+        assert_eq!(select.client_conditions.len(), 2);
+
+        let ((chunked, matches_nothing), select) = planned(sql, [5, 0, 40]);
+        assert_eq!((chunked, matches_nothing), (Some(2), false));
+        assert_eq!(
+            select.conditions,
+            vec![
+                list("c", 1, 5),
+                Condition::Comparison(
+                    FieldPath(vec!["d".to_owned()]),
+                    CompareOperations::Equal(Value::Integer(2))
+                ),
+                list("f", 3, 40),
+            ]
+        );
+        assert!(select.client_conditions.is_empty());
+
+        let ((chunked, matches_nothing), _) = planned(sql, [5, 5, 0]);
+        assert_eq!((chunked, matches_nothing), (None, true));
+
+        // conditions checked on the client side refer to the main collection by its alias
+        let ((chunked, _), select) = planned(
+            "select o.a from orders o join users u on o.userId = u.:id where o.c in (1) or o.d in (2)",
+            [40, 40, 0],
+        );
+        assert_eq!(chunked, None);
+        assert!(select.conditions.is_empty());
+        assert_eq!(
+            select.client_conditions,
+            vec![Condition::Or(vec![list("o.c", 1, 40), list("o.d", 2, 40)])]
+        );
+    }
+
+    #[test]
+    fn resolved_references() {
+        let mut select = FireSQLParser::parse(
+            r#"select a from b
+            where author = ref(projects/abc)
+            or author = ref("projects/p-1/databases/(default)/documents/users/a-b")"#,
+        )
+        .unwrap();
+        resolve_references(&mut select, "projects/p-2/databases/(default)/documents");
+        let mut references = vec![];
+        select.visit_values_mut(&mut |value| {
+            if let Value::Reference(path) = value {
+                references.push(path.clone());
+            }
+        });
+        assert_eq!(
+            references,
+            vec![
+                "projects/p-2/databases/(default)/documents/projects/abc",
+                "projects/p-1/databases/(default)/documents/users/a-b",
+            ]
+        );
+    }
+}
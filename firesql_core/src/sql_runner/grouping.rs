@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+
+use gcloud_sdk::google::firestore::v1::{value::ValueType, Document, Value};
+use itertools::Itertools as _;
+
+use super::field_value;
+use super::references::References;
+use super::values::{compare_values, is_null, numeric_value, operation_matches, ValueKey};
+use crate::sql_parser::{Aggregation, FieldPath, HavingCondition, HavingOperand, SelectProjection};
+
+/// Groups fetched documents by values of the GROUP BY fields and calculates
+/// the aggregations of every group. Without GROUP BY all documents are a single group.
+/// Returns the projected values of every group.
+pub(super) fn group_documents(
+    documents: &[Document],
+    projections: &[SelectProjection],
+    group_by: &[FieldPath],
+    having: Option<&HavingCondition>,
+) -> Vec<Vec<Option<Value>>> {
+    // groups are kept in the order of their first documents
+    let mut groups: Vec<Vec<&Document>> = vec![];
+    let mut group_indexes: HashMap<Vec<Option<ValueKey>>, usize> = HashMap::new();
+    for document in documents {
+        let key = group_by
+            .iter()
+            .map(|field| field_value(&document.fields, field).cloned().map(ValueKey))
+            .collect_vec();
+        let index = *group_indexes.entry(key).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+        groups[index].push(document);
+    }
+    if group_by.is_empty() && groups.is_empty() {
+        groups.push(vec![]);
+    }
+
+    groups
+        .into_iter()
+        .filter(|group| having.map_or(true, |having| having_matches(having, group)))
+        .map(|group| {
+            projections
+                .iter()
+                .map(|projection| match projection {
+                    SelectProjection::Property(field, _) => group_field(&group, field),
+                    SelectProjection::Aggregate(aggregation, _) => {
+                        Some(aggregate(aggregation, &group))
+                    }
+                    _ => unreachable!("only grouped fields and aggregations are projected"),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Removes documents with the same values of projected fields as one of the previous
+/// documents. Values are compared by their types, e.g. `1` and `"1"` are different.
+pub(super) fn distinct_documents(
+    documents: Vec<Document>,
+    projections: &[SelectProjection],
+    references: &References,
+) -> Vec<Document> {
+    let mut keys = HashSet::new();
+    documents
+        .into_iter()
+        .filter(|document| {
+            let key = projections
+                .iter()
+                .map(|projection| match projection {
+                    SelectProjection::Property(field, _) => {
+                        field_value(&document.fields, field).cloned().map(ValueKey)
+                    }
+                    SelectProjection::Dereference(paths, _) => references
+                        .value(&document.fields, paths)
+                        .cloned()
+                        .map(ValueKey),
+                    // document names are unique
+                    _ => Some(ValueKey(Value {
+                        value_type: Some(ValueType::ReferenceValue(document.name.clone())),
+                    })),
+                })
+                .collect_vec();
+            keys.insert(key)
+        })
+        .collect()
+}
+
+/// Value of the GROUP BY field - the same for all documents in the group.
+fn group_field(group: &[&Document], field: &FieldPath) -> Option<Value> {
+    group
+        .first()
+        .and_then(|document| field_value(&document.fields, field))
+        .cloned()
+}
+
+fn aggregate(aggregation: &Aggregation, group: &[&Document]) -> Value {
+    let values = || {
+        group.iter().filter_map(|document| {
+            let field = aggregation.field().expect("aggregated field");
+            field_value(&document.fields, field).filter(|value| !is_null(value))
+        })
+    };
+    let value_type = match aggregation {
+        Aggregation::CountAll => ValueType::IntegerValue(group.len() as i64),
+        Aggregation::Count(_) => ValueType::IntegerValue(values().count() as i64),
+        Aggregation::Sum(_) => {
+            // sum of integers stays an integer unless it overflows
+            let mut integer_sum = Some(0i64);
+            let mut double_sum = 0.0;
+            for value in values() {
+                match value.value_type.as_ref() {
+                    Some(ValueType::IntegerValue(value)) => {
+                        integer_sum = integer_sum.and_then(|sum| sum.checked_add(*value));
+                        double_sum += *value as f64;
+                    }
+                    Some(ValueType::DoubleValue(value)) => {
+                        integer_sum = None;
+                        double_sum += value;
+                    }
+                    _ => {}
+                }
+            }
+            match integer_sum {
+                Some(sum) => ValueType::IntegerValue(sum),
+                None => ValueType::DoubleValue(double_sum),
+            }
+        }
+        Aggregation::Avg(_) => {
+            let numbers = values().filter_map(numeric_value).collect_vec();
+            match numbers.is_empty() {
+                true => ValueType::NullValue(0),
+                false => ValueType::DoubleValue(numbers.iter().sum::<f64>() / numbers.len() as f64),
+            }
+        }
+        Aggregation::Min(_) => {
+            return values()
+                .min_by(|a, b| compare_values(a, b))
+                .cloned()
+                .unwrap_or_else(null_value)
+        }
+        Aggregation::Max(_) => {
+            return values()
+                .max_by(|a, b| compare_values(a, b))
+                .cloned()
+                .unwrap_or_else(null_value)
+        }
+    };
+    Value {
+        value_type: Some(value_type),
+    }
+}
+
+fn null_value() -> Value {
+    Value {
+        value_type: Some(ValueType::NullValue(0)),
+    }
+}
+
+fn having_matches(having: &HavingCondition, group: &[&Document]) -> bool {
+    match having {
+        HavingCondition::And(conditions) => conditions
+            .iter()
+            .all(|condition| having_matches(condition, group)),
+        HavingCondition::Or(conditions) => conditions
+            .iter()
+            .any(|condition| having_matches(condition, group)),
+        HavingCondition::Not(condition) => !having_matches(condition, group),
+        HavingCondition::Comparison(operand, operation) => {
+            let value = match operand {
+                HavingOperand::Field(field) => group_field(group, field),
+                HavingOperand::Aggregate(aggregation) => Some(aggregate(aggregation, group)),
+            };
+            value.is_some_and(|value| operation_matches(&value, operation))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parser::FireSQLParser;
+    use crate::sql_runner::test_fixtures::{column_values, order, value};
+    use crate::sql_runner::{apply_limit, values_row, Row};
+
+    fn grouped_rows(values: Vec<Vec<Option<Value>>>, projections: &[SelectProjection]) -> Vec<Row> {
+        values
+            .iter()
+            .map(|values| values_row(projections, values))
+            .collect()
+    }
+
+    #[test]
+    fn groups_with_having() {
+        let documents = [
+            order("a", "new", 10),
+            order("b", "paid", 5),
+            order("c", "new", 20),
+            order("d", "sent", 1),
+            order("e", "paid", 7),
+        ];
+        let select = FireSQLParser::parse(
+            r#"select status, count(*) as orders, sum(total), avg(total), min(total), max(total)
+            from orders group by status having orders > 1"#,
+        )
+        .unwrap();
+        let rows = group_documents(
+            &documents,
+            &select.projections,
+            &select.group_by,
+            select.having.as_ref(),
+        );
+        let rows = grouped_rows(rows, &select.projections);
+        let rows = rows.iter().map(|row| row.columns().clone()).collect_vec();
+        let row = |values: [&str; 6]| {
+            [
+                "status",
+                "orders",
+                "SUM(total)",
+                "AVG(total)",
+                "MIN(total)",
+                "MAX(total)",
+            ]
+            .into_iter()
+            .zip(values)
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect_vec()
+        };
+        assert_eq!(
+            rows,
+            vec![
+                row(["new", "2", "30", "15", "10", "20"]),
+                row(["paid", "2", "12", "6", "5", "7"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn limited_groups() {
+        let documents = [
+            order("a", "new", 10),
+            order("b", "paid", 5),
+            order("c", "new", 20),
+            order("d", "sent", 1),
+        ];
+        let select = FireSQLParser::parse(
+            "select status, count(*) from orders group by status limit 2 offset 1",
+        )
+        .unwrap();
+        let rows = group_documents(&documents, &select.projections, &select.group_by, None);
+        let rows = grouped_rows(
+            apply_limit(rows, select.limit, select.offset),
+            &select.projections,
+        );
+        assert_eq!(
+            column_values(&rows),
+            vec![vec!["paid", "1"], vec!["sent", "1"]]
+        );
+    }
+
+    #[test]
+    fn distinct_values() {
+        let mut documents = vec![
+            order("a", "new", 10),
+            order("b", "new", 10),
+            order("c", "new", 5),
+            order("d", "paid", 10),
+        ];
+        documents[1]
+            .fields
+            .insert("total".to_owned(), value(ValueType::DoubleValue(10.0)));
+        documents[3].fields.remove("status");
+        documents.push(order("e", "10", 10));
+        let select = FireSQLParser::parse("select distinct status, total from orders").unwrap();
+        let names = distinct_documents(documents, &select.projections, &References::default())
+            .into_iter()
+            .map(|document| document.name)
+            .collect_vec();
+        assert_eq!(names, vec!["a", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn single_group_without_group_by() {
+        let select = FireSQLParser::parse("select count(*), max(total) from orders").unwrap();
+        let rows = group_documents(&[], &select.projections, &select.group_by, None);
+        let rows = grouped_rows(rows, &select.projections);
+        assert_eq!(
+            rows[0].columns(),
+            &vec![
+                ("COUNT(*)".to_owned(), "0".to_owned()),
+                ("MAX(total)".to_owned(), "NULL".to_owned())
+            ]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use firestore::{FirestoreDb, FirestoreValue};
+use gcloud_sdk::google::firestore::v1::{value::ValueType, ArrayValue, Document, MapValue, Value};
+use itertools::Itertools as _;
+
+use super::references::get_documents;
+use super::values::{is_null, ValueKey};
+use super::{field_value, from_collection, ExecutionError, IN_VALUES_LIMIT};
+use crate::sql_parser::{Collection, Join, JoinKind, DOCUMENT_ID_FIELD};
+
+/// Joins the documents of the main collection with the documents of joined collections.
+/// Fields of the joined documents are maps under the collection aliases, so
+/// `o.total` is a field path in the joined document. Documents keep the names
+/// of the main collection documents.
+pub(super) async fn join_documents(
+    db: &FirestoreDb,
+    documents: Vec<Document>,
+    alias: &str,
+    joins: &[Join],
+) -> Result<Vec<Document>, ExecutionError> {
+    let mut documents = documents
+        .into_iter()
+        .map(|document| {
+            let fields = [(alias.to_owned(), aliased_document(&document))].into();
+            Document { fields, ..document }
+        })
+        .collect_vec();
+    for join in joins {
+        let matches = match join.right.is_document_id() {
+            true => documents_by_id(db, &documents, join).await?,
+            false => documents_by_field(db, &documents, join).await?,
+        };
+        documents = documents
+            .into_iter()
+            .flat_map(|document| {
+                let joined = field_value(&document.fields, &join.left)
+                    .filter(|value| !is_null(value))
+                    .and_then(|value| matches.get(&ValueKey(value.clone())))
+                    .cloned()
+                    .unwrap_or_default();
+                match (joined.is_empty(), join.kind) {
+                    (true, JoinKind::Left) => vec![document],
+                    _ => joined
+                        .into_iter()
+                        .map(|joined| {
+                            let mut document = document.clone();
+                            document.fields.insert(join.alias.clone(), joined);
+                            document
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+    }
+    Ok(documents)
+}
+
+/// Document fields with its id as a map value.
+fn aliased_document(document: &Document) -> Value {
+    let id = document
+        .name
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    let mut fields = document.fields.clone();
+    fields.insert(
+        DOCUMENT_ID_FIELD.to_owned(),
+        Value {
+            value_type: Some(ValueType::StringValue(id)),
+        },
+    );
+    Value {
+        value_type: Some(ValueType::MapValue(MapValue { fields })),
+    }
+}
+
+/// Batch-gets the joined documents referenced by the ids (or references) in the joined field.
+async fn documents_by_id(
+    db: &FirestoreDb,
+    documents: &[Document],
+    join: &Join,
+) -> Result<HashMap<ValueKey, Vec<Value>>, ExecutionError> {
+    let (_, documents_path) = from_collection(db, db.fluent().select(), &join.collection)?;
+    // joined values are matched with the names of the documents
+    let names = documents
+        .iter()
+        .filter_map(|document| field_value(&document.fields, &join.left))
+        .filter_map(|value| match value.value_type.as_ref()? {
+            ValueType::StringValue(id) if !id.contains('/') => {
+                Some((value.clone(), format!("{documents_path}/{id}")))
+            }
+            // references to documents of other collections do not match
+            ValueType::ReferenceValue(name)
+                if in_collection(name, &documents_path, &join.collection) =>
+            {
+                Some((value.clone(), name.clone()))
+            }
+            _ => None,
+        })
+        .unique_by(|(value, _)| ValueKey(value.clone()))
+        .collect_vec();
+    let joined = get_documents(
+        db,
+        &names.iter().map(|(_, name)| name.clone()).collect_vec(),
+    )
+    .await?;
+    Ok(names
+        .into_iter()
+        .filter_map(|(value, name)| {
+            let document = joined.get(&name)?;
+            Some((ValueKey(value), vec![aliased_document(document)]))
+        })
+        .collect())
+}
+
+/// Whether the document name belongs to the collection (or collection group) whose
+/// documents are under the documents path.
+fn in_collection(name: &str, documents_path: &str, collection: &Collection) -> bool {
+    let Some(path) = name
+        .strip_prefix(documents_path)
+        .and_then(|path| path.strip_prefix('/'))
+    else {
+        return false;
+    };
+    if !collection.all_descendants {
+        return !path.contains('/');
+    }
+    // collection group documents are under the database root, in collections
+    // with the group name nested in the parent document, if there is one
+    let (parent, group) = collection
+        .path
+        .rsplit_once('/')
+        .unwrap_or(("", &collection.path));
+    let segments = path.split('/').collect_vec();
+    segments.len() >= 2
+        && segments[segments.len() - 2] == group
+        && (parent.is_empty() || path.starts_with(&format!("{parent}/")))
+}
+
+/// Queries the joined collection for documents with the values of the joined field.
+async fn documents_by_field(
+    db: &FirestoreDb,
+    documents: &[Document],
+    join: &Join,
+) -> Result<HashMap<ValueKey, Vec<Value>>, ExecutionError> {
+    let values = documents
+        .iter()
+        .filter_map(|document| field_value(&document.fields, &join.left))
+        .filter(|value| !is_null(value))
+        .unique_by(|value| ValueKey((*value).clone()))
+        .cloned()
+        .collect_vec();
+    let queries = values.chunks(IN_VALUES_LIMIT).map(|values| async move {
+        let (query, _) = from_collection(db, db.fluent().select(), &join.collection)?;
+        let values = FirestoreValue::from(Value {
+            value_type: Some(ValueType::ArrayValue(ArrayValue {
+                values: values.to_vec(),
+            })),
+        });
+        let documents = query
+            .filter(|f| f.field(join.right.to_string()).is_in(values.clone()))
+            .query()
+            .await?;
+        Ok::<_, ExecutionError>(documents)
+    });
+    let mut joined: HashMap<ValueKey, Vec<Value>> = HashMap::new();
+    for document in futures::future::try_join_all(queries)
+        .await?
+        .into_iter()
+        .flatten()
+    {
+        if let Some(value) = field_value(&document.fields, &join.right) {
+            joined
+                .entry(ValueKey(value.clone()))
+                .or_default()
+                .push(aliased_document(&document));
+        }
+    }
+    Ok(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joined_references() {
+        let root = "projects/p/databases/d/documents";
+        let users = Collection {
+            path: "users".to_owned(),
+            all_descendants: false,
+        };
+        let users_path = format!("{root}/users");
+        assert!(in_collection(
+            &format!("{root}/users/u1"),
+            &users_path,
+            &users
+        ));
+        assert!(!in_collection(
+            &format!("{root}/admins/u1"),
+            &users_path,
+            &users
+        ));
+        assert!(!in_collection(
+            &format!("{root}/users_old/u1"),
+            &users_path,
+            &users
+        ));
+        assert!(!in_collection(
+            &format!("{root}/users/u1/posts/p1"),
+            &users_path,
+            &users
+        ));
+
+        let posts = Collection {
+            path: "posts".to_owned(),
+            all_descendants: true,
+        };
+        assert!(in_collection(
+            &format!("{root}/users/u1/posts/p1"),
+            root,
+            &posts
+        ));
+        assert!(!in_collection(&format!("{root}/users/u1"), root, &posts));
+        let user_posts = Collection {
+            path: "users/u1/posts".to_owned(),
+            all_descendants: true,
+        };
+        assert!(in_collection(
+            &format!("{root}/users/u1/drafts/d1/posts/p1"),
+            root,
+            &user_posts
+        ));
+        assert!(!in_collection(
+            &format!("{root}/users/u2/posts/p1"),
+            root,
+            &user_posts
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use firestore::{FirestoreDb, FirestoreGetByIdSupport};
+use futures::TryStreamExt as _;
+use gcloud_sdk::google::firestore::v1::{value::ValueType, Document, Value};
+use itertools::Itertools as _;
+
+use super::{field_value, ExecutionError};
+use crate::sql_parser::{Condition, FieldPath, SelectProjection};
+
+/// Documents referenced by dereferenced fields (`author->name`) by their names.
+#[derive(Debug, Default)]
+pub(super) struct References(HashMap<String, Document>);
+
+impl References {
+    /// Batch-gets the documents referenced by the chains of reference fields level by level,
+    /// so every referenced document is fetched once.
+    pub(super) async fn fetch(
+        db: &FirestoreDb,
+        documents: &[Document],
+        chains: &[Vec<FieldPath>],
+    ) -> Result<Self, ExecutionError> {
+        let mut references = Self::default();
+        let depth = chains.iter().map(Vec::len).max().unwrap_or_default();
+        for level in 0..depth {
+            let names = documents
+                .iter()
+                .cartesian_product(chains.iter().filter(|chain| chain.len() > level))
+                .filter_map(|(document, chain)| {
+                    match references
+                        .value(&document.fields, &chain[..=level])?
+                        .value_type
+                        .as_ref()?
+                    {
+                        ValueType::ReferenceValue(name) => Some(name.clone()),
+                        _ => None,
+                    }
+                })
+                .filter(|name| !references.0.contains_key(name))
+                .unique()
+                .collect_vec();
+            references.0.extend(get_documents(db, &names).await?);
+        }
+        Ok(references)
+    }
+
+    /// Fields of the document referenced by the value.
+    pub(super) fn referenced_fields(&self, reference: &Value) -> Option<&HashMap<String, Value>> {
+        match reference.value_type.as_ref()? {
+            ValueType::ReferenceValue(name) => self.0.get(name).map(|document| &document.fields),
+            _ => None,
+        }
+    }
+
+    /// Value of the last field of the dereference chain, e.g. `author->name`.
+    pub(super) fn value<'a>(
+        &'a self,
+        fields: &'a HashMap<String, Value>,
+        paths: &[FieldPath],
+    ) -> Option<&'a Value> {
+        let (first, rest) = paths.split_first()?;
+        rest.iter()
+            .try_fold(field_value(fields, first)?, |reference, path| {
+                field_value(self.referenced_fields(reference)?, path)
+            })
+    }
+}
+
+/// Chains of reference fields that are dereferenced by the projections and the conditions.
+pub(super) fn dereferenced_fields(
+    projections: &[SelectProjection],
+    conditions: &[Condition],
+) -> Vec<Vec<FieldPath>> {
+    fn condition_chains(
+        condition: &Condition,
+        chain: &[FieldPath],
+        chains: &mut Vec<Vec<FieldPath>>,
+    ) {
+        match condition {
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions {
+                    condition_chains(condition, chain, chains);
+                }
+            }
+            Condition::Not(condition) => condition_chains(condition, chain, chains),
+            Condition::Dereference(reference, condition) => {
+                let chain = chain.iter().chain([reference]).cloned().collect_vec();
+                condition_chains(condition, &chain, chains);
+                chains.push(chain);
+            }
+            Condition::IsNull(_) | Condition::Comparison(..) => {}
+        }
+    }
+
+    let mut chains = projections
+        .iter()
+        .filter_map(|projection| match projection {
+            SelectProjection::Dereference(paths, _) => Some(paths[..paths.len() - 1].to_vec()),
+            _ => None,
+        })
+        .collect_vec();
+    for condition in conditions {
+        condition_chains(condition, &[], &mut chains);
+    }
+    chains.into_iter().unique().collect()
+}
+
+/// Batch-gets documents by their full names, grouped by their collections.
+/// Missing documents are skipped.
+pub(super) async fn get_documents(
+    db: &FirestoreDb,
+    names: &[String],
+) -> Result<HashMap<String, Document>, ExecutionError> {
+    let collections = names
+        .iter()
+        .filter_map(|name| {
+            let (collection_path, id) = name.rsplit_once('/')?;
+            let (parent, collection_id) = collection_path.rsplit_once('/')?;
+            Some(((parent, collection_id), id))
+        })
+        .unique()
+        .into_group_map();
+    let batches = collections
+        .into_iter()
+        .map(|((parent, collection_id), ids)| async move {
+            db.batch_stream_get_docs_at_with_errors(parent, collection_id, ids, None)
+                .await?
+                .try_collect::<Vec<_>>()
+                .await
+        });
+    Ok(futures::future::try_join_all(batches)
+        .await?
+        .into_iter()
+        .flatten()
+        .filter_map(|(_, document)| document)
+        .map(|document| (document.name.clone(), document))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parser::FireSQLParser;
+
+    fn reference(name: &str) -> Value {
+        Value {
+            value_type: Some(ValueType::ReferenceValue(name.to_owned())),
+        }
+    }
+
+    #[test]
+    fn dereferenced_values() {
+        let document = |name: &str, fields: Vec<(&str, Value)>| Document {
+            name: name.to_owned(),
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect(),
+            ..Default::default()
+        };
+        let references = References(
+            [
+                document(
+                    "users/a",
+                    vec![
+                        (
+                            "name",
+                            Value {
+                                value_type: Some(ValueType::StringValue("Ann".to_owned())),
+                            },
+                        ),
+                        ("manager", reference("users/b")),
+                    ],
+                ),
+                document(
+                    "users/b",
+                    vec![(
+                        "name",
+                        Value {
+                            value_type: Some(ValueType::StringValue("Bob".to_owned())),
+                        },
+                    )],
+                ),
+            ]
+            .into_iter()
+            .map(|document| (document.name.clone(), document))
+            .collect(),
+        );
+        let post = document("posts/1", vec![("author", reference("users/a"))]);
+        let select = FireSQLParser::parse(
+            "select author->name, author->manager->name, author->x->name from posts",
+        )
+        .unwrap();
+        let values = select
+            .projections
+            .iter()
+            .map(|projection| match projection {
+                SelectProjection::Dereference(paths, _) => references
+                    .value(&post.fields, paths)
+                    .and_then(|value| value.value_type.clone()),
+                _ => unreachable!(),
+            })
+            .collect_vec();
+        assert_eq!(
+            values,
+            vec![
+                Some(ValueType::StringValue("Ann".to_owned())),
+                Some(ValueType::StringValue("Bob".to_owned())),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn dereference_chains() {
+        let select = FireSQLParser::parse(
+            "select author->name from posts where author->manager->name = 'Bob' or editor->active = true",
+        )
+        .unwrap();
+        let field = |name: &str| FieldPath(vec![name.to_owned()]);
+        assert_eq!(
+            dereferenced_fields(&select.projections, &select.client_conditions),
+            vec![
+                vec![field("author")],
+                vec![field("author"), field("manager")],
+                vec![field("editor")],
+            ]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+
+use firestore::FirestoreDb;
+use gcloud_sdk::google::firestore::v1::{value::ValueType, Document, Value};
+use itertools::Itertools as _;
+
+use super::references::References;
+use super::values::ValueKey;
+use super::{
+    column_value, document_row, select_documents, values_row, ExecutionError, ExecutionOptions,
+    Row, Selected,
+};
+use crate::sql_parser::{FireSQLSelect, SelectProjection, SetOperator};
+
+/// Row of the combined statement together with the key it is compared by.
+pub(super) type KeyedRow = (Row, Vec<Option<ValueKey>>);
+
+/// Rows of statements selecting documents (`*`) are compared by document paths.
+/// Rows of other statements are compared by all their values, including document ids.
+pub(super) fn combined_by_path(selects: &[FireSQLSelect]) -> bool {
+    selects
+        .iter()
+        .all(|select| select.projections.contains(&SelectProjection::Object))
+}
+
+/// Rows of the statement keyed by their document paths or by the typed values
+/// of their columns.
+pub(super) async fn select_keyed_rows(
+    db: &FirestoreDb,
+    select: FireSQLSelect,
+    options: &ExecutionOptions,
+    by_path: bool,
+) -> Result<Vec<KeyedRow>, ExecutionError> {
+    let projections = select.projections.clone();
+    let rows = match select_documents(db, select, options).await? {
+        Selected::Rows(rows) => rows
+            .into_iter()
+            .map(|values| {
+                let row = values_row(&projections, &values);
+                (
+                    row,
+                    values
+                        .into_iter()
+                        .map(|value| value.map(ValueKey))
+                        .collect(),
+                )
+            })
+            .collect(),
+        Selected::Documents(documents, references) => documents
+            .into_iter()
+            .map(|document| {
+                let key = document_key(&document, &projections, &references, by_path);
+                (document_row(document, &projections, &references), key)
+            })
+            .collect(),
+    };
+    Ok(rows)
+}
+
+/// Key of the document row: its path or the typed values of its columns.
+fn document_key(
+    document: &Document,
+    projections: &[SelectProjection],
+    references: &References,
+    by_path: bool,
+) -> Vec<Option<ValueKey>> {
+    match by_path {
+        true => vec![Some(ValueKey(Value {
+            value_type: Some(ValueType::ReferenceValue(document.name.clone())),
+        }))],
+        false => projections
+            .iter()
+            .map(|projection| column_value(document, projection, references).map(ValueKey))
+            .collect(),
+    }
+}
+
+/// Combines the rows of the first statement with the rows of the following statements.
+/// INTERSECT binds tighter than UNION and EXCEPT, which are applied from left to right.
+/// Columns are named after the columns of the first statement.
+pub(super) fn combine_rows(
+    names: &[String],
+    rows: Vec<KeyedRow>,
+    operations: Vec<(SetOperator, Vec<KeyedRow>)>,
+) -> Vec<Row> {
+    let mut terms = vec![(None, rows)];
+    for (operator, rows) in operations {
+        let rows = rows
+            .into_iter()
+            .map(|(Row(id, columns), key)| {
+                let columns = names
+                    .iter()
+                    .zip(columns)
+                    .map(|(name, (_, value))| (name.clone(), value))
+                    .collect();
+                (Row(id, columns), key)
+            })
+            .collect_vec();
+        match (operator, terms.last_mut()) {
+            (SetOperator::Intersect, Some((_, term))) => {
+                let keys: HashSet<_> = rows.into_iter().map(|(_, key)| key).collect();
+                *term = distinct_rows(std::mem::take(term))
+                    .into_iter()
+                    .filter(|(_, key)| keys.contains(key))
+                    .collect();
+            }
+            _ => terms.push((Some(operator), rows)),
+        }
+    }
+
+    terms
+        .into_iter()
+        .reduce(|(_, combined), (operator, rows)| {
+            let combined = match operator {
+                Some(SetOperator::UnionAll) => combined.into_iter().chain(rows).collect(),
+                Some(SetOperator::Except) => {
+                    let keys: HashSet<_> = rows.into_iter().map(|(_, key)| key).collect();
+                    distinct_rows(combined)
+                        .into_iter()
+                        .filter(|(_, key)| !keys.contains(key))
+                        .collect()
+                }
+                _ => distinct_rows(combined.into_iter().chain(rows).collect()),
+            };
+            (None, combined)
+        })
+        .map(|(_, rows)| rows.into_iter().map(|(row, _)| row).collect())
+        .unwrap_or_default()
+}
+
+/// Rows without the rows with the same key as one of the previous rows.
+fn distinct_rows(rows: Vec<KeyedRow>) -> Vec<KeyedRow> {
+    let mut keys = HashSet::new();
+    rows.into_iter()
+        .filter(|(_, key)| keys.insert(key.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parser::FireSQLParser;
+    use crate::sql_runner::firestore_value_to_string;
+    use crate::sql_runner::test_fixtures::{ids, order, string, value};
+
+    /// Row with the single `status` column keyed by the document path.
+    fn document(id: &str, status: &str) -> KeyedRow {
+        let (row, _) = row(id, string(status));
+        let key = vec![Some(ValueKey(value(ValueType::ReferenceValue(
+            id.to_owned(),
+        ))))];
+        (row, key)
+    }
+
+    /// Row with the single `status` column keyed by its value.
+    fn row(id: &str, status: Value) -> KeyedRow {
+        let row = Row(
+            id.to_owned(),
+            vec![("status".to_owned(), firestore_value_to_string(&status))],
+        );
+        (row, vec![Some(ValueKey(status))])
+    }
+
+    fn names() -> Vec<String> {
+        vec!["status".to_owned()]
+    }
+
+    #[test]
+    fn combined_rows() {
+        let a = || {
+            vec![
+                document("a", "new"),
+                document("b", "paid"),
+                document("a", "new"),
+            ]
+        };
+        let b = || vec![document("b", "paid"), document("c", "new")];
+
+        let union = combine_rows(&names(), a(), vec![(SetOperator::Union, b())]);
+        assert_eq!(ids(&union), vec!["a", "b", "c"]);
+        let union_all = combine_rows(&names(), a(), vec![(SetOperator::UnionAll, b())]);
+        assert_eq!(ids(&union_all), vec!["a", "b", "a", "b", "c"]);
+        let intersect = combine_rows(&names(), a(), vec![(SetOperator::Intersect, b())]);
+        assert_eq!(ids(&intersect), vec!["b"]);
+        let except = combine_rows(&names(), a(), vec![(SetOperator::Except, b())]);
+        assert_eq!(ids(&except), vec!["a"]);
+
+        // INTERSECT is applied before UNION ALL
+        let combined = combine_rows(
+            &names(),
+            a(),
+            vec![
+                (SetOperator::UnionAll, b()),
+                (SetOperator::Intersect, vec![document("c", "sent")]),
+            ],
+        );
+        assert_eq!(ids(&combined), vec!["a", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn combined_values() {
+        let a = || {
+            vec![
+                row("a", string("new")),
+                row("b", string("paid")),
+                row("c", string("new")),
+            ]
+        };
+        let b = || vec![row("d", string("paid"))];
+        let union = combine_rows(&names(), a(), vec![(SetOperator::Union, b())]);
+        assert_eq!(ids(&union), vec!["a", "b"]);
+        let except = combine_rows(&names(), a(), vec![(SetOperator::Except, b())]);
+        assert_eq!(ids(&except), vec!["a"]);
+
+        // values are compared by their types, not by their text
+        let union = combine_rows(
+            &names(),
+            vec![
+                row("a", value(ValueType::IntegerValue(1))),
+                row("b", value(ValueType::NullValue(0))),
+            ],
+            vec![(
+                SetOperator::Union,
+                vec![
+                    row("c", string("1")),
+                    row("d", string("NULL")),
+                    row("e", value(ValueType::DoubleValue(1.0))),
+                ],
+            )],
+        );
+        assert_eq!(ids(&union), vec!["a", "b", "c", "d"]);
+
+        // maps are equal regardless of the order of their fields
+        let map = |fields: Vec<(&str, i64)>| {
+            value(ValueType::MapValue(
+                gcloud_sdk::google::firestore::v1::MapValue {
+                    fields: fields
+                        .into_iter()
+                        .map(|(key, number)| {
+                            (key.to_owned(), value(ValueType::IntegerValue(number)))
+                        })
+                        .collect(),
+                },
+            ))
+        };
+        let intersect = combine_rows(
+            &names(),
+            vec![row("a", map(vec![("x", 1), ("y", 2)]))],
+            vec![(
+                SetOperator::Intersect,
+                vec![row("b", map(vec![("y", 2), ("x", 1)]))],
+            )],
+        );
+        assert_eq!(ids(&intersect), vec!["a"]);
+    }
+
+    #[test]
+    fn combined_column_names() {
+        let rows = combine_rows(
+            &names(),
+            vec![row("a", string("new"))],
+            vec![(
+                SetOperator::Union,
+                vec![(
+                    Row(
+                        "b".to_owned(),
+                        vec![("state".to_owned(), "paid".to_owned())],
+                    ),
+                    vec![Some(ValueKey(string("paid")))],
+                )],
+            )],
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row(
+                    "a".to_owned(),
+                    vec![("status".to_owned(), "new".to_owned())]
+                ),
+                Row(
+                    "b".to_owned(),
+                    vec![("status".to_owned(), "paid".to_owned())]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn combining_by_projection() {
+        let selects = |sql: &str| {
+            let mut select = FireSQLParser::parse(sql).unwrap();
+            let mut selects = vec![select.clone()];
+            selects.extend(select.set_operations.drain(..).map(|(_, select)| select));
+            selects
+        };
+        assert!(combined_by_path(&selects(
+            "select * from users union select * from admins"
+        )));
+        assert!(!combined_by_path(&selects(
+            "select :id, name from users except select name, :id from admins"
+        )));
+        assert!(!combined_by_path(&selects(
+            "select :id, name from users intersect select name, email from admins"
+        )));
+    }
+
+    #[test]
+    fn combined_document_ids() {
+        // documents of different collections with the same id select the same `:id` row
+        let select = FireSQLParser::parse("select :id, status from orders").unwrap();
+        let by_path = combined_by_path(std::slice::from_ref(&select));
+        let keyed = |name: &str| {
+            let document = order(name, "new", 10);
+            let key = document_key(
+                &document,
+                &select.projections,
+                &References::default(),
+                by_path,
+            );
+            let row = document_row(document, &select.projections, &References::default());
+            (row, key)
+        };
+        let names = vec![":id".to_owned(), "status".to_owned()];
+        let union = combine_rows(
+            &names,
+            vec![keyed("orders/o1"), keyed("orders/o2")],
+            vec![(SetOperator::Union, vec![keyed("archive/o1")])],
+        );
+        assert_eq!(ids(&union), vec!["orders/o1", "orders/o2"]);
+        let intersect = combine_rows(
+            &names,
+            vec![keyed("orders/o1"), keyed("orders/o2")],
+            vec![(SetOperator::Intersect, vec![keyed("archive/o1")])],
+        );
+        assert_eq!(ids(&intersect), vec!["orders/o1"]);
+    }
+}
@@ -0,0 +1,44 @@
+//! Values, documents and rows shared by the tests of the runner.
+
+use gcloud_sdk::google::firestore::v1::{value::ValueType, Document, Value};
+
+use super::Row;
+
+pub(super) fn value(value_type: ValueType) -> Value {
+    Value {
+        value_type: Some(value_type),
+    }
+}
+
+pub(super) fn string(text: &str) -> Value {
+    value(ValueType::StringValue(text.to_owned()))
+}
+
+/// Document of the `orders` collection with the `status` and `total` fields.
+pub(super) fn order(name: &str, status: &str, total: i64) -> Document {
+    Document {
+        name: name.to_owned(),
+        fields: [
+            ("status".to_owned(), string(status)),
+            ("total".to_owned(), value(ValueType::IntegerValue(total))),
+        ]
+        .into(),
+        ..Default::default()
+    }
+}
+
+pub(super) fn ids(rows: &[Row]) -> Vec<&str> {
+    rows.iter().map(Row::id).collect()
+}
+
+/// Values of the columns of every row.
+pub(super) fn column_values(rows: &[Row]) -> Vec<Vec<&str>> {
+    rows.iter()
+        .map(|row| {
+            row.columns()
+                .iter()
+                .map(|(_, value)| value.as_str())
+                .collect()
+        })
+        .collect()
+}
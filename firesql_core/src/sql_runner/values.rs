@@ -0,0 +1,376 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use firestore::FirestoreValue;
+use gcloud_sdk::google::firestore::v1::{value::ValueType, Value};
+use itertools::Itertools as _;
+
+use super::references::References;
+use super::{field_value, ValueWrapper};
+use crate::sql_parser::{CompareOperations, Condition, Value as StatementValue};
+
+/// Position of the value type in Firestore ordering. Integers and doubles
+/// are compared with each other as numbers.
+fn type_order(value_type: &ValueType) -> u8 {
+    match value_type {
+        ValueType::NullValue(_) => 0,
+        ValueType::BooleanValue(_) => 1,
+        ValueType::IntegerValue(_) | ValueType::DoubleValue(_) => 2,
+        ValueType::TimestampValue(_) => 3,
+        ValueType::StringValue(_) => 4,
+        ValueType::BytesValue(_) => 5,
+        ValueType::ReferenceValue(_) => 6,
+        ValueType::GeoPointValue(_) => 7,
+        ValueType::ArrayValue(_) => 8,
+        ValueType::MapValue(_) => 9,
+    }
+}
+
+fn value_type(value: &Value) -> &ValueType {
+    value
+        .value_type
+        .as_ref()
+        .expect("Value should contain its type")
+}
+
+/// Compares values the way Firestore orders them.
+pub(super) fn compare_values(a: &Value, b: &Value) -> Ordering {
+    let (a, b) = (value_type(a), value_type(b));
+    match (a, b) {
+        (ValueType::NullValue(_), ValueType::NullValue(_)) => Ordering::Equal,
+        (ValueType::BooleanValue(a), ValueType::BooleanValue(b)) => a.cmp(b),
+        (ValueType::IntegerValue(a), ValueType::IntegerValue(b)) => a.cmp(b),
+        (ValueType::IntegerValue(a), ValueType::DoubleValue(b)) => compare_doubles(*a as f64, *b),
+        (ValueType::DoubleValue(a), ValueType::IntegerValue(b)) => compare_doubles(*a, *b as f64),
+        (ValueType::DoubleValue(a), ValueType::DoubleValue(b)) => compare_doubles(*a, *b),
+        (ValueType::TimestampValue(a), ValueType::TimestampValue(b)) => {
+            (a.seconds, a.nanos).cmp(&(b.seconds, b.nanos))
+        }
+        (ValueType::StringValue(a), ValueType::StringValue(b)) => a.cmp(b),
+        (ValueType::BytesValue(a), ValueType::BytesValue(b)) => a.cmp(b),
+        (ValueType::ReferenceValue(a), ValueType::ReferenceValue(b)) => {
+            a.split('/').cmp(b.split('/'))
+        }
+        (ValueType::GeoPointValue(a), ValueType::GeoPointValue(b)) => {
+            compare_doubles(a.latitude, b.latitude)
+                .then_with(|| compare_doubles(a.longitude, b.longitude))
+        }
+        (ValueType::ArrayValue(a), ValueType::ArrayValue(b)) => a
+            .values
+            .iter()
+            .zip(b.values.iter())
+            .map(|(a, b)| compare_values(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.values.len().cmp(&b.values.len())),
+        (ValueType::MapValue(a), ValueType::MapValue(b)) => {
+            let a_entries = a.fields.iter().sorted_by_key(|(key, _)| *key);
+            let b_entries = b.fields.iter().sorted_by_key(|(key, _)| *key);
+            a_entries
+                .zip(b_entries)
+                .map(|((a_key, a_value), (b_key, b_value))| {
+                    a_key
+                        .cmp(b_key)
+                        .then_with(|| compare_values(a_value, b_value))
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.fields.len().cmp(&b.fields.len()))
+        }
+        (a, b) => type_order(a).cmp(&type_order(b)),
+    }
+}
+
+/// NaN is equal to itself and smaller than any other number.
+fn compare_doubles(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a.partial_cmp(&b).expect("numbers are comparable"),
+    }
+}
+
+/// Numeric value of an integer or a double.
+pub(super) fn numeric_value(value: &Value) -> Option<f64> {
+    match value_type(value) {
+        ValueType::IntegerValue(value) => Some(*value as f64),
+        ValueType::DoubleValue(value) => Some(*value),
+        _ => None,
+    }
+}
+
+pub(super) fn is_null(value: &Value) -> bool {
+    matches!(value_type(value), ValueType::NullValue(_))
+}
+
+/// Checks if values are of the same type, so they can be compared with `<`, `>` etc.
+pub(super) fn same_type_order(a: &Value, b: &Value) -> bool {
+    type_order(value_type(a)) == type_order(value_type(b))
+}
+
+/// Checks the condition on the client side the way Firestore filters check documents.
+pub(super) fn condition_matches(
+    fields: &HashMap<String, Value>,
+    condition: &Condition,
+    references: &References,
+) -> bool {
+    match condition {
+        Condition::And(conditions) => conditions
+            .iter()
+            .all(|condition| condition_matches(fields, condition, references)),
+        Condition::Or(conditions) => conditions
+            .iter()
+            .any(|condition| condition_matches(fields, condition, references)),
+        Condition::Not(condition) => match condition.as_ref() {
+            // missing fields are neither null nor not null
+            Condition::IsNull(field) => field_value(fields, field).is_some_and(|v| !is_null(v)),
+            condition => !condition_matches(fields, condition, references),
+        },
+        Condition::IsNull(field) => field_value(fields, field).is_some_and(is_null),
+        Condition::Comparison(field, operation) => {
+            field_value(fields, field).is_some_and(|value| operation_matches(value, operation))
+        }
+        Condition::Dereference(reference, condition) => field_value(fields, reference)
+            .and_then(|reference| references.referenced_fields(reference))
+            .is_some_and(|fields| condition_matches(fields, condition, references)),
+    }
+}
+
+/// Checks the value the way Firestore filters check document fields.
+pub(super) fn operation_matches(value: &Value, operation: &CompareOperations) -> bool {
+    let to_value = |value| Into::<FirestoreValue>::into(ValueWrapper::from(value)).value;
+    let equal = |expected| compare_values(value, &to_value(expected)).is_eq();
+    let compare = |expected| {
+        let expected = to_value(expected);
+        same_type_order(value, &expected).then(|| compare_values(value, &expected))
+    };
+    match operation {
+        CompareOperations::Equal(expected) => equal(expected),
+        CompareOperations::NotEqual(expected) => !is_null(value) && !equal(expected),
+        CompareOperations::GreaterThan(expected) => compare(expected).is_some_and(|o| o.is_gt()),
+        CompareOperations::GreaterThanOrEqual(expected) => {
+            compare(expected).is_some_and(|o| o.is_ge())
+        }
+        CompareOperations::LessThan(expected) => compare(expected).is_some_and(|o| o.is_lt()),
+        CompareOperations::LessThanOrEqual(expected) => {
+            compare(expected).is_some_and(|o| o.is_le())
+        }
+        CompareOperations::In(values) => values.iter().any(equal),
+        CompareOperations::NotIn(values) => !is_null(value) && !values.iter().any(equal),
+        CompareOperations::ArrayContains(expected) => array_values(value)
+            .iter()
+            .any(|item| compare_values(item, &to_value(expected)).is_eq()),
+        CompareOperations::ArrayContainsAny(expected) => array_values(value).iter().any(|item| {
+            expected
+                .iter()
+                .any(|expected| compare_values(item, &to_value(expected)).is_eq())
+        }),
+        CompareOperations::InSubquery(_) => unreachable!("subqueries are run before the query"),
+    }
+}
+
+fn array_values(value: &Value) -> &[Value] {
+    match value.value_type.as_ref() {
+        Some(ValueType::ArrayValue(array)) => &array.values,
+        _ => &[],
+    }
+}
+
+/// Converts the document value to the statement value, e.g. to compare fields
+/// with the values selected by a subquery.
+pub(super) fn statement_value(value: &Value) -> StatementValue {
+    match value_type(value) {
+        ValueType::NullValue(_) => StatementValue::Null,
+        ValueType::BooleanValue(value) => StatementValue::Bool(*value),
+        ValueType::IntegerValue(value) => StatementValue::Integer(*value),
+        ValueType::DoubleValue(value) => StatementValue::Double(*value),
+        ValueType::TimestampValue(timestamp) => {
+            StatementValue::Timestamp(timestamp.seconds, timestamp.nanos)
+        }
+        ValueType::StringValue(value) => StatementValue::String(value.clone()),
+        ValueType::BytesValue(bytes) => StatementValue::Bytes(bytes.clone()),
+        ValueType::ReferenceValue(name) => StatementValue::Reference(name.clone()),
+        ValueType::GeoPointValue(lat_lng) => {
+            StatementValue::GeoPoint(lat_lng.latitude, lat_lng.longitude)
+        }
+        ValueType::ArrayValue(array) => {
+            StatementValue::Array(array.values.iter().map(statement_value).collect())
+        }
+        ValueType::MapValue(map) => StatementValue::Map(
+            map.fields
+                .iter()
+                .map(|(key, value)| (key.clone(), statement_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Value that can be used as a key of hash maps and sets. Keys are equal when
+/// Firestore considers the values equal, e.g. `1` and `1.0`.
+#[derive(Debug, Clone)]
+pub(super) struct ValueKey(pub(super) Value);
+
+impl PartialEq for ValueKey {
+    fn eq(&self, other: &Self) -> bool {
+        compare_values(&self.0, &other.0).is_eq()
+    }
+}
+
+impl Eq for ValueKey {}
+
+impl Hash for ValueKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.0, state)
+    }
+}
+
+fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+    let value_type = value_type(value);
+    type_order(value_type).hash(state);
+    match value_type {
+        ValueType::NullValue(_) => {}
+        ValueType::BooleanValue(value) => value.hash(state),
+        ValueType::IntegerValue(value) => value.hash(state),
+        ValueType::DoubleValue(value) => hash_double(*value, state),
+        ValueType::TimestampValue(value) => (value.seconds, value.nanos).hash(state),
+        ValueType::StringValue(value) => value.hash(state),
+        ValueType::BytesValue(value) => value.hash(state),
+        ValueType::ReferenceValue(value) => value.hash(state),
+        ValueType::GeoPointValue(value) => {
+            hash_double(value.latitude, state);
+            hash_double(value.longitude, state);
+        }
+        ValueType::ArrayValue(array) => {
+            array.values.len().hash(state);
+            for value in array.values.iter() {
+                hash_value(value, state);
+            }
+        }
+        ValueType::MapValue(map) => {
+            map.fields.len().hash(state);
+            for (key, value) in map.fields.iter().sorted_by_key(|(key, _)| *key) {
+                key.hash(state);
+                hash_value(value, state);
+            }
+        }
+    }
+}
+
+/// Doubles with integral values are hashed as integers, so they match equal integers.
+fn hash_double<H: Hasher>(value: f64, state: &mut H) {
+    if value.is_nan() {
+        f64::NAN.to_bits().hash(state);
+    } else if value.fract() == 0.0 && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+        (value as i64).hash(state);
+    } else {
+        value.to_bits().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::sql_parser::FireSQLParser;
+    use crate::sql_runner::test_fixtures::value;
+
+    #[test]
+    fn values_ordering() {
+        let ordered = [
+            value(ValueType::NullValue(0)),
+            value(ValueType::BooleanValue(false)),
+            value(ValueType::BooleanValue(true)),
+            value(ValueType::DoubleValue(f64::NAN)),
+            value(ValueType::IntegerValue(-1)),
+            value(ValueType::DoubleValue(0.5)),
+            value(ValueType::IntegerValue(1)),
+            value(ValueType::StringValue("a".to_owned())),
+            value(ValueType::StringValue("b".to_owned())),
+            value(ValueType::BytesValue(vec![0])),
+            value(ValueType::ReferenceValue("a/b".to_owned())),
+            value(ValueType::ReferenceValue("a/b/c/d".to_owned())),
+        ];
+        for (a, b) in ordered.iter().tuple_windows() {
+            assert_eq!(compare_values(a, b), Ordering::Less, "{a:?} < {b:?}");
+            assert_eq!(compare_values(b, a), Ordering::Greater, "{b:?} > {a:?}");
+        }
+    }
+
+    #[test]
+    fn value_keys() {
+        let keys = [
+            ValueKey(value(ValueType::IntegerValue(1))),
+            ValueKey(value(ValueType::DoubleValue(1.0))),
+            ValueKey(value(ValueType::DoubleValue(1.5))),
+            ValueKey(value(ValueType::StringValue("1".to_owned()))),
+            ValueKey(value(ValueType::NullValue(0))),
+        ];
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[3]);
+        assert_eq!(keys.iter().cloned().collect::<HashSet<_>>().len(), 4);
+    }
+
+    #[test]
+    fn statement_values() {
+        let map = value(ValueType::MapValue(
+            gcloud_sdk::google::firestore::v1::MapValue {
+                fields: [(
+                    "a".to_owned(),
+                    value(ValueType::ArrayValue(
+                        gcloud_sdk::google::firestore::v1::ArrayValue {
+                            values: vec![
+                                value(ValueType::IntegerValue(1)),
+                                value(ValueType::NullValue(0)),
+                            ],
+                        },
+                    )),
+                )]
+                .into(),
+            },
+        ));
+        assert_eq!(
+            statement_value(&map),
+            StatementValue::Map(
+                [(
+                    "a".to_owned(),
+                    StatementValue::Array(vec![StatementValue::Integer(1), StatementValue::Null])
+                )]
+                .into()
+            )
+        );
+        assert_eq!(
+            statement_value(&value(ValueType::ReferenceValue("a/b".to_owned()))),
+            StatementValue::Reference("a/b".to_owned())
+        );
+    }
+
+    #[test]
+    fn client_side_conditions() {
+        let fields: HashMap<_, _> = [
+            ("total".to_owned(), value(ValueType::IntegerValue(10))),
+            ("note".to_owned(), value(ValueType::NullValue(0))),
+            (
+                "author".to_owned(),
+                value(ValueType::ReferenceValue("users/missing".to_owned())),
+            ),
+        ]
+        .into();
+        let matches = |conditions: &str| {
+            let select = FireSQLParser::parse(&format!(
+                "select total from orders where author->name = 'x' or {conditions}"
+            ))
+            .unwrap();
+            select
+                .client_conditions
+                .iter()
+                .all(|condition| condition_matches(&fields, condition, &References::default()))
+        };
+        assert!(matches("total > 5"));
+        assert!(!matches("total > 10"));
+        assert!(matches("total = 10 and other is null or note is null"));
+        assert!(!matches("other is not null or note is not null"));
+        assert!(matches("total in (1, 10)"));
+        assert!(!matches("not author->name = 'x'"));
+    }
+}